@@ -0,0 +1,286 @@
+//! Writing out a documented `tomlfmt.toml`, optionally with settings inferred from
+//! the manifests it will apply to.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use toml_edit::{Document, Item, RawString, Value};
+
+use super::Config;
+use crate::IoResult;
+
+/// The comment written above every key of a generated config, in the order the keys
+/// are written out.
+pub(super) const DOCS: &[(&str, &str)] = &[
+    (
+        "preset",
+        "The settings the others start out with, \"cargo-style-guide\", \"taplo-default\", \
+         \"minimal-diff\" or \"strict\".",
+    ),
+    (
+        "always_trailing_comma",
+        "Use a trailing comma in every array, even single-line ones.",
+    ),
+    (
+        "multiline_trailing_comma",
+        "Use a trailing comma in arrays that span multiple lines.",
+    ),
+    ("space_around_eq", "Put a space on both sides of the `=` of key value pairs."),
+    ("compact_arrays", "Omit the whitespace padding inside single-line arrays."),
+    ("compact_inline_tables", "Omit the whitespace padding inside inline tables."),
+    (
+        "max_width",
+        "The column arrays and inline tables are kept within, longer ones get one element \
+         per line.",
+    ),
+    ("indent_string", "The indentation of array elements written one per line."),
+    (
+        "formatter",
+        "What formats the document after sorting, \"off\", \"builtin\" or \"taplo\".",
+    ),
+    (
+        "quote_style",
+        "How strings are quoted, \"preserve\", \"double\" or \"single\", strings that \
+         would need escapes keep their quotes.",
+    ),
+    ("unquote_keys", "Drop the quotes of keys that do not need them."),
+    (
+        "normalize_comments",
+        "Put a single space after the `#` of comments, `#!` and `##` comments are left alone.",
+    ),
+    (
+        "align_comments",
+        "Align the trailing comments of consecutive lines, the ones that do not fit in \
+         `max_width` go above their line.",
+    ),
+    (
+        "align_entries",
+        "Line up the `=` of consecutive key value lines, a blank line or a comment starts a new block.",
+    ),
+    (
+        "clean_tables",
+        "Drop empty tables and redundant headers, and inline `[dependencies.name]` tables that fit.",
+    ),
+    ("trailing_newline", "End the file with a newline."),
+    (
+        "key_value_newlines",
+        "Allow blank lines between the key value pairs of a table, they are needed to \
+         keep groups of keys.",
+    ),
+    ("allowed_blank_lines", "The maximum number of consecutive blank lines."),
+    ("crlf", "Use windows style line endings."),
+    (
+        "table_order",
+        "The order top level tables are written in, tables not listed here come after \
+         these.",
+    ),
+    (
+        "workspace_dependency_grouping",
+        "Where `workspace = true` dependencies go within a dependency table, \"top\" or \
+         \"bottom\".",
+    ),
+    (
+        "sort_rustflags",
+        "Sort the `rustflags` arrays of `.cargo/config.toml` files, off by default since \
+         the order of flags can matter.",
+    ),
+    (
+        "order_sensitive_arrays",
+        "Glob patterns of the dotted paths of arrays that are never reordered, setting \
+         this replaces the built-in list.",
+    ),
+    (
+        "sort_array_of_tables",
+        "Sort the entries of top level arrays of tables like `[[bin]]` and `[[test]]`.",
+    ),
+    ("array_of_tables_key", "The key the entries of arrays of tables are sorted by."),
+    (
+        "sort_collation",
+        "How keys and array elements are compared, \"ascii\", \"case-insensitive\" or \
+         \"natural\" which also orders numbers by their value.",
+    ),
+    (
+        "dash_underscore_equal",
+        "Treat `-` and `_` as the same character when sorting, like cargo does for crate \
+         names.",
+    ),
+    (
+        "dependency_groups",
+        "Named groups of dependencies written before the rest, matched by glob, \
+         /regex/, @path, @git or @workspace.",
+    ),
+    (
+        "dependency_group_headers",
+        "Separate the dependency groups by a blank line and a `# <group>` comment.",
+    ),
+];
+
+/// The values written, commented out, for keys that are unset by default.
+const UNSET_EXAMPLES: &[(&str, &str)] = &[
+    ("preset", "\"cargo-style-guide\""),
+    ("workspace_dependency_grouping", "\"top\""),
+    (
+        "dependency_groups",
+        "{ internal = [\"acme-*\"], async = [\"tokio*\", \"futures*\"] }",
+    ),
+];
+
+/// `value` written on one line, `toml` writes a table as the lines of a document.
+fn inline(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Table(table) if table.is_empty() => "{}".to_owned(),
+        toml::Value::Table(table) => {
+            let pairs = table.iter().map(|(key, val)| {
+                format!("{} = {}", toml_edit::Key::new(key).display_repr(), inline(val))
+            });
+            format!("{{ {} }}", pairs.collect::<Vec<_>>().join(", "))
+        }
+        toml::Value::Array(arr) => {
+            format!("[{}]", arr.iter().map(inline).collect::<Vec<_>>().join(", "))
+        }
+        val => val.to_string(),
+    }
+}
+
+impl Config {
+    /// Renders the config as a `tomlfmt.toml` with every key documented.
+    ///
+    /// Keys without a value are written commented out.
+    pub(crate) fn to_commented_toml(&self) -> IoResult<String> {
+        let toml::Value::Table(table) = toml::Value::try_from(self)? else {
+            unreachable!("the config is a struct")
+        };
+
+        let mut out = String::new();
+        for (idx, (key, doc)) in DOCS.iter().enumerate() {
+            if idx > 0 {
+                out.push('\n');
+            }
+            writeln!(out, "# {}", doc)?;
+            match table.get(*key) {
+                Some(toml::Value::Array(arr)) if !arr.is_empty() => {
+                    writeln!(out, "{} = [", key)?;
+                    for val in arr {
+                        writeln!(out, "    {},", inline(val))?;
+                    }
+                    writeln!(out, "]")?;
+                }
+                Some(val) => writeln!(out, "{} = {}", key, inline(val))?,
+                None => {
+                    let example = UNSET_EXAMPLES.iter().find(|(k, _)| k == key);
+                    writeln!(out, "# {} = {}", key, example.map_or("", |(_, ex)| ex))?
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Adjusts the line endings, table order and trailing comma settings to what
+    /// most of `manifests` already use.
+    pub(crate) fn infer_from(&mut self, manifests: &[String]) {
+        let mut crlf = Votes::default();
+        let mut multiline_comma = Votes::default();
+        let mut single_line_comma = Votes::default();
+        // The position of each table name relative to the length of the document it
+        // was found in, summed up, and how often it was found
+        let mut positions: BTreeMap<String, (f64, usize)> = BTreeMap::new();
+
+        for raw in manifests {
+            let Ok(doc) = raw.parse::<Document>() else {
+                continue;
+            };
+            crlf.vote(raw.contains("\r\n"));
+
+            let tables = doc
+                .iter()
+                .filter(|(_, item)| item.is_table() || item.is_array_of_tables())
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            for (idx, key) in tables.iter().enumerate() {
+                let pos = positions.entry(key.to_string()).or_default();
+                pos.0 += idx as f64 / tables.len() as f64;
+                pos.1 += 1;
+            }
+
+            visit_arrays(doc.as_item(), &mut |arr| {
+                if arr.is_empty() {
+                    return;
+                }
+                let multiline = arr.iter().any(|v| has_newline(v.decor().prefix()))
+                    || has_newline(Some(arr.trailing()));
+                if multiline {
+                    multiline_comma.vote(arr.trailing_comma());
+                } else {
+                    single_line_comma.vote(arr.trailing_comma());
+                }
+            });
+        }
+
+        if let Some(crlf) = crlf.winner() {
+            self.crlf = crlf;
+        }
+        if let Some(comma) = multiline_comma.winner() {
+            self.multiline_trailing_comma = comma;
+        }
+        if let Some(comma) = single_line_comma.winner() {
+            self.always_trailing_comma = comma;
+        }
+        if !positions.is_empty() {
+            let mut order = positions
+                .into_iter()
+                .map(|(key, (sum, count))| (sum / count as f64, key))
+                .collect::<Vec<_>>();
+            order.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+            self.table_order = order.into_iter().map(|(_, key)| key).collect();
+        }
+    }
+}
+
+/// Counts the yes and no answers to a question about the manifests.
+#[derive(Default)]
+struct Votes {
+    yes: usize,
+    no: usize,
+}
+
+impl Votes {
+    fn vote(&mut self, yes: bool) {
+        if yes {
+            self.yes += 1;
+        } else {
+            self.no += 1;
+        }
+    }
+
+    /// The majority answer, or `None` if no one voted or there was a tie.
+    fn winner(&self) -> Option<bool> {
+        (self.yes != self.no).then_some(self.yes > self.no)
+    }
+}
+
+fn has_newline(raw: Option<&RawString>) -> bool {
+    raw.and_then(RawString::as_str).is_some_and(|s| s.contains('\n'))
+}
+
+fn visit_arrays(item: &Item, f: &mut dyn FnMut(&toml_edit::Array)) {
+    match item {
+        Item::Value(val) => visit_value_arrays(val, f),
+        Item::Table(table) => table.iter().for_each(|(_, item)| visit_arrays(item, f)),
+        Item::ArrayOfTables(arr) => {
+            arr.iter().flat_map(|t| t.iter()).for_each(|(_, item)| visit_arrays(item, f))
+        }
+        Item::None => {}
+    }
+}
+
+fn visit_value_arrays(val: &Value, f: &mut dyn FnMut(&toml_edit::Array)) {
+    match val {
+        Value::Array(arr) => {
+            f(arr);
+            arr.iter().for_each(|v| visit_value_arrays(v, f));
+        }
+        Value::InlineTable(table) => {
+            table.iter().for_each(|(_, v)| visit_value_arrays(v, f))
+        }
+        _ => {}
+    }
+}
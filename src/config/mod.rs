@@ -0,0 +1,641 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, Visitor};
+use toml::Table;
+
+pub use self::preset::Preset;
+use crate::IoResult;
+
+mod init;
+mod preset;
+mod test;
+
+/// The names of the config file, looked for in every directory in this order.
+const CONFIG_FILES: &[&str] = &["tomlfmt.toml", ".tomlfmt.toml"];
+
+/// The config file for formatting toml after sorting.
+///
+/// Use the `FromStr` to create a config from a string.
+///
+/// ## Example
+/// ```
+/// let input = "trailing_comma = true\ncrlf = true";
+/// let config = input.parse::<Config>().unwrap();
+/// assert!(config.trailing_comma);
+/// assert!(config.crlf);
+/// ```
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The named set of settings the other keys start out with, the keys that are set
+    /// override it.
+    #[serde(default)]
+    pub preset: Option<Preset>,
+
+    /// Use trailing comma where possible.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub always_trailing_comma: bool,
+
+    /// Use trailing comma for multi-line arrays.
+    ///
+    /// Defaults to `true`.
+    #[serde(default = "default_as_true")]
+    pub multiline_trailing_comma: bool,
+
+    /// Use space around equal sign for table key values.
+    ///
+    /// Defaults to `true`.
+    #[serde(default = "default_as_true")]
+    pub space_around_eq: bool,
+
+    /// Omit whitespace padding inside single-line arrays.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub compact_arrays: bool,
+
+    /// Omit whitespace padding inside inline tables.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub compact_inline_tables: bool,
+
+    /// The column arrays and inline tables are kept within, longer ones are written
+    /// with one element per line and shorter multi-line ones are joined onto one.
+    ///
+    /// Defaults to `100`.
+    #[serde(default = "Config::default_max_width")]
+    pub max_width: usize,
+
+    /// The indentation of the elements of arrays written one element per line.
+    ///
+    /// Defaults to four spaces.
+    #[serde(default = "Config::default_indent_string")]
+    pub indent_string: String,
+
+    /// What formats the document after sorting, `"builtin"`, `"taplo"` or `"off"`.
+    ///
+    /// Defaults to `"off"`, the sorted document keeps the layout it had.
+    #[serde(default)]
+    pub formatter: Formatter,
+
+    /// How strings are quoted, `"preserve"`, `"double"` or `"single"`.
+    ///
+    /// A literal string that would need escapes as a basic one stays literal, and a
+    /// basic string that can not be literal stays basic. Rewritten basic strings only
+    /// escape what has to be. Defaults to `"preserve"`.
+    #[serde(default)]
+    pub quote_style: QuoteStyle,
+
+    /// Drop the quotes of keys that do not need them, `"serde" = "1"` becomes
+    /// `serde = "1"`.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub unquote_keys: bool,
+
+    /// Put a single space after the `#` of comments, comments starting with `#!` or `##`
+    /// are left alone.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub normalize_comments: bool,
+
+    /// Start the trailing comments of consecutive key value lines at the same column,
+    /// and move a trailing comment that would go past `max_width` above its line.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub align_comments: bool,
+
+    /// Pad the keys of consecutive key value lines so their `=` line up, a blank line
+    /// or a comment starts a new block.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub align_entries: bool,
+
+    /// Drop the empty `[features]`, `[badges]` and dependency tables and the headers
+    /// with nothing but other tables under them, and write `[dependencies.name]` tables
+    /// as inline tables of their dependency table where they fit. Tables with comments
+    /// are kept as written, and only `Cargo.toml` manifests are cleaned.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub clean_tables: bool,
+
+    /// Add trailing newline to the source.
+    ///
+    /// Defaults to `true`.
+    #[serde(default = "default_as_true")]
+    pub trailing_newline: bool,
+
+    /// Are newlines allowed between key value pairs in a table.
+    ///
+    /// This must be true for the `--grouped` flag to be used.
+    /// Defaults to `true`.
+    #[serde(default = "default_as_true")]
+    pub key_value_newlines: bool,
+
+    /// The maximum amount of consecutive blank lines allowed.
+    ///
+    /// Defaults to `1`.
+    #[serde(default = "default_as_one")]
+    pub allowed_blank_lines: usize,
+
+    // NOTE: this is only used in main, fmt doesn't set the line endings
+    /// Use CRLF line endings
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub crlf: bool,
+
+    /// The user specified ordering of tables in a document.
+    ///
+    /// All unspecified tables will come after these. An empty list keeps every table
+    /// in its place, only the nested tables of a dependency table are sorted among
+    /// the places they take.
+    #[serde(default = "Config::default_table_order")]
+    pub table_order: Vec<String>,
+
+    #[serde(default)]
+    pub workspace_dependency_grouping: Option<WSDependencyGrouping>,
+
+    /// Sort the `rustflags` arrays of `.cargo/config.toml` files.
+    ///
+    /// The order of flags can matter so this is opt in, the arrays are also protected
+    /// by `order_sensitive_arrays` until their patterns are removed from it.
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub sort_rustflags: bool,
+
+    /// Glob patterns matching the dotted paths of arrays that are never reordered,
+    /// like `target.*.rustflags`.
+    ///
+    /// Setting this replaces the built-in list, leave a pattern out to allow sorting
+    /// the arrays it matches.
+    #[serde(default = "Config::default_order_sensitive_arrays")]
+    pub order_sensitive_arrays: Vec<String>,
+
+    /// Sort the entries of top level arrays of tables, like `[[bin]]` and `[[test]]`,
+    /// by their `array_of_tables_key`.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub sort_array_of_tables: bool,
+
+    /// The key the entries of arrays of tables are sorted by.
+    ///
+    /// Defaults to `"name"`.
+    #[serde(default = "Config::default_array_of_tables_key")]
+    pub array_of_tables_key: String,
+
+    /// How keys and array elements are compared when sorting.
+    ///
+    /// Defaults to `"ascii"`.
+    #[serde(default)]
+    pub sort_collation: Collation,
+
+    /// Treat `-` and `_` as the same character when sorting, like cargo does for crate
+    /// names.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub dash_underscore_equal: bool,
+
+    /// Named groups of dependencies, written in this order before the dependencies no
+    /// group matches.
+    ///
+    /// A group lists glob patterns like `acme-*`, regular expressions between slashes
+    /// like `/^tokio(-.*)?$/`, or the attributes `@path`, `@git` and `@workspace`.
+    #[serde(default, skip_serializing_if = "DependencyGroups::is_empty")]
+    pub dependency_groups: DependencyGroups,
+
+    /// Separate the dependency groups by a blank line and a `# <group>` comment.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub dependency_group_headers: bool,
+}
+
+fn default_as_true() -> bool { true }
+
+fn default_as_one() -> usize { 1 }
+
+const DEFAULT_TABLE_ORDER: &[&str] = &[
+    "package",
+    "lib",
+    "bin",
+    "features",
+    "dependencies",
+    "build-dependencies",
+    "dev-dependencies",
+];
+
+/// Arrays where the order of the elements changes their meaning.
+const DEFAULT_ORDER_SENSITIVE_ARRAYS: &[&str] = &[
+    "build.rustflags",
+    "build.rustdocflags",
+    "host.rustflags",
+    "target.*.rustflags",
+    "target.*.rustdocflags",
+    "target.*.runner",
+    "alias.*",
+    "*.args",
+    "*-args",
+    "*.required-features",
+];
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WSDependencyGrouping {
+    Top,
+    Bottom,
+}
+
+/// The formatter that runs after sorting.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Formatter {
+    /// Nothing, the sorted document is written as it is.
+    #[default]
+    Off,
+    /// The layout rules of `crate::fmt`.
+    Builtin,
+    /// taplo's formatter, with the options it shares with this config mapped onto it.
+    Taplo,
+}
+
+/// The quotes strings are written with.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteStyle {
+    /// Strings keep the quotes they have.
+    #[default]
+    Preserve,
+    /// Basic strings, `"like this"`.
+    Double,
+    /// Literal strings, `'like this'`.
+    Single,
+}
+
+/// How strings are compared when sorting.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Collation {
+    /// By their bytes, so `Serde` comes before `anyhow`.
+    #[default]
+    Ascii,
+    /// Ignoring the case of ASCII letters.
+    CaseInsensitive,
+    /// Ignoring case and comparing numbers by their value, so `crate-2` comes before
+    /// `crate-10`.
+    Natural,
+}
+
+/// The `dependency_groups` of a config, in the order they are written.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DependencyGroups(pub Vec<(String, Vec<String>)>);
+
+impl DependencyGroups {
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+}
+
+impl serde::Serialize for DependencyGroups {
+    fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.collect_map(self.0.iter().map(|(name, patterns)| (name, patterns)))
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DependencyGroups {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> serde::de::Visitor<'de> for Visitor {
+            type Value = DependencyGroups;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("a table of group names to lists of patterns")
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                mut map: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut groups = vec![];
+                while let Some(group) = map.next_entry()? {
+                    groups.push(group);
+                }
+                Ok(DependencyGroups(groups))
+            }
+        }
+
+        de.deserialize_map(Visitor)
+    }
+}
+
+impl Config {
+    pub fn serde_default() -> Self { toml::from_str("").unwrap() }
+    fn default_max_width() -> usize { 100 }
+    fn default_indent_string() -> String { "    ".to_owned() }
+    fn default_table_order() -> Vec<String> {
+        DEFAULT_TABLE_ORDER.iter().map(ToString::to_string).collect()
+    }
+    fn default_order_sensitive_arrays() -> Vec<String> {
+        DEFAULT_ORDER_SENSITIVE_ARRAYS.iter().map(ToString::to_string).collect()
+    }
+    fn default_array_of_tables_key() -> String { "name".to_owned() }
+
+    /// Returns the `order_sensitive_arrays` pattern matching the dotted `path` of an
+    /// array, if the array must keep its order.
+    pub(crate) fn order_sensitive(&self, path: &str) -> Option<&str> {
+        self.order_sensitive_arrays
+            .iter()
+            .find(|pat| glob::Pattern::new(pat).is_ok_and(|pat| pat.matches(path)))
+            .map(String::as_str)
+    }
+
+    /// Resolves the config that applies to the manifest at `manifest`.
+    ///
+    /// If `explicit` is given only that file is used. Otherwise every directory from
+    /// the workspace root down to the manifest's own directory is searched, and the
+    /// settings found further down override the ones found above them one key at a
+    /// time. Within a directory a `tomlfmt.toml` overrides the
+    /// `[workspace.metadata.manifmt]` and `[package.metadata.manifmt]` sections of
+    /// the directory's `Cargo.toml`. `preset` replaces the preset the files select.
+    pub(crate) fn resolve(
+        manifest: &Path,
+        explicit: Option<&Path>,
+        preset: Option<Preset>,
+    ) -> IoResult<Self> {
+        if let Some(path) = explicit {
+            return Self::from_table(read_config(path)?, path, preset);
+        }
+
+        let mut merged = Table::new();
+        for dir in config_dirs(manifest).iter().rev() {
+            let cargo_toml = dir.join("Cargo.toml");
+            if cargo_toml.is_file() {
+                merged.extend(read_metadata(&cargo_toml)?);
+            }
+            if let Some(path) =
+                CONFIG_FILES.iter().map(|f| dir.join(f)).find(|p| p.is_file())
+            {
+                merged.extend(read_config(&path)?);
+            }
+        }
+        Self::from_table(merged, manifest, preset)
+    }
+
+    /// The config `table` describes, on top of the settings of `preset` or else the
+    /// preset it selects.
+    fn from_table(
+        mut table: Table,
+        origin: &Path,
+        preset: Option<Preset>,
+    ) -> IoResult<Self> {
+        if let Some(preset) = preset {
+            table.insert("preset".to_owned(), toml::Value::try_from(preset)?);
+        }
+        if let Some(preset) = table.get("preset") {
+            let preset: Preset = preset
+                .clone()
+                .try_into()
+                .map_err(|e| format!("invalid config for {}: {}", origin.display(), e))?;
+            let mut settings = preset.settings();
+            settings.extend(table);
+            table = settings;
+        }
+        let config: Self = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("invalid config for {}: {}", origin.display(), e))?;
+        for pat in config.dependency_groups.0.iter().flat_map(|(_, pats)| pats) {
+            crate::sort::DependencyRule::parse(pat).map_err(|e| {
+                format!(
+                    "invalid config for {}: bad pattern `{}` in dependency_groups: {}",
+                    origin.display(),
+                    pat,
+                    e
+                )
+            })?;
+        }
+        for pat in &config.order_sensitive_arrays {
+            glob::Pattern::new(pat).map_err(|e| {
+                format!(
+                    "invalid config for {}: bad pattern `{}` in order_sensitive_arrays: {}",
+                    origin.display(),
+                    pat,
+                    e
+                )
+            })?;
+        }
+        Ok(config)
+    }
+
+    // Used in testing and fuzzing
+    #[allow(dead_code)]
+    pub(crate) fn new() -> Self { toml::from_str("").unwrap() }
+}
+
+/// Returns the directories whose config applies to `manifest`, innermost first.
+///
+/// The walk stops at the first directory holding a workspace manifest, a crate
+/// outside of any workspace picks up every config up to the filesystem root.
+pub(crate) fn config_dirs(manifest: &Path) -> Vec<PathBuf> {
+    let manifest = if manifest.is_absolute() {
+        manifest.to_path_buf()
+    } else {
+        std::env::current_dir().map(|cwd| cwd.join(manifest)).unwrap_or_default()
+    };
+    let start =
+        if manifest.is_dir() { Some(manifest.as_path()) } else { manifest.parent() };
+
+    let mut dirs = vec![];
+    for dir in start.into_iter().flat_map(Path::ancestors) {
+        dirs.push(dir.to_path_buf());
+        if is_workspace_root(dir) {
+            break;
+        }
+    }
+    dirs
+}
+
+pub(crate) fn is_workspace_root(dir: &Path) -> bool {
+    read_to_string(dir.join("Cargo.toml"))
+        .ok()
+        .and_then(|s| s.parse::<Table>().ok())
+        .is_some_and(|t| t.contains_key("workspace"))
+}
+
+fn read_config(path: &Path) -> IoResult<Table> {
+    let raw = read_to_string(path)
+        .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
+    validate::<Config>(&raw, path)?;
+    raw.parse::<Table>()
+        .map_err(|e| format!("failed to parse config {}: {}", path.display(), e).into())
+}
+
+/// Reads the `[workspace.metadata.manifmt]` and `[package.metadata.manifmt]`
+/// sections of a `Cargo.toml`, the package section takes precedence.
+fn read_metadata(cargo_toml: &Path) -> IoResult<Table> {
+    let raw = read_to_string(cargo_toml)
+        .map_err(|e| format!("failed to read {}: {}", cargo_toml.display(), e))?;
+    let manifest = raw
+        .parse::<Table>()
+        .map_err(|e| format!("failed to parse {}: {}", cargo_toml.display(), e))?;
+
+    let mut merged = Table::new();
+    for section in ["workspace", "package"] {
+        if let Some(toml::Value::Table(meta)) = manifest
+            .get(section)
+            .and_then(|s| s.get("metadata"))
+            .and_then(|m| m.get("manifmt"))
+        {
+            validate_metadata(&raw, section, cargo_toml)?;
+            merged.extend(meta.clone());
+        }
+    }
+    Ok(merged)
+}
+
+/// Checks the `[<section>.metadata.manifmt]` table of the manifest `raw` like
+/// `validate` checks a config file, pointing at the line and column in `cargo_toml`.
+fn validate_metadata(raw: &str, section: &str, cargo_toml: &Path) -> IoResult<()> {
+    let path = [section, "metadata", "manifmt"];
+    match ConfigAt(&path).deserialize(toml::Deserializer::new(raw)) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(located(&e, raw, cargo_toml)),
+    }
+}
+
+/// Deserializes the table at `path` of a document as a `Config`, skipping the rest.
+struct ConfigAt<'a>(&'a [&'a str]);
+
+impl<'de> DeserializeSeed<'de> for ConfigAt<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<(), D::Error> {
+        de.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for ConfigAt<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a table")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match self.0 {
+                [first] if *first == key => {
+                    map.next_value::<Config>()?;
+                }
+                [first, rest @ ..] if *first == key => {
+                    map.next_value_seed(ConfigAt(rest))?;
+                }
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Deserializes `raw` as a `T` only to report the first mistake in it, pointing at
+/// the line and column in `path` where it was made.
+fn validate<T: serde::de::DeserializeOwned>(raw: &str, path: &Path) -> IoResult<()> {
+    match toml::from_str::<T>(raw) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(located(&e, raw, path)),
+    }
+}
+
+/// The message of `e` prefixed with `path` and the line and column in `raw` it
+/// points at.
+fn located(e: &toml::de::Error, raw: &str, path: &Path) -> Box<dyn Error> {
+    let msg = match unknown_field(e.message()) {
+        Some((field, expected)) => unknown_key(field, &expected),
+        None => e.message().to_string(),
+    };
+    match e.span() {
+        Some(span) => {
+            let before = &raw[..span.start];
+            let line = before.matches('\n').count() + 1;
+            let col = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+            format!("{}:{}:{}: {}", path.display(), line, col, msg).into()
+        }
+        None => format!("{}: {}", path.display(), msg).into(),
+    }
+}
+
+/// Picks the field name and the expected field names out of serde's
+/// "unknown field `x`, expected one of `a`, `b`" message.
+fn unknown_field(msg: &str) -> Option<(&str, Vec<&str>)> {
+    let rest = msg.strip_prefix("unknown field `")?;
+    let (field, rest) = rest.split_once('`')?;
+    let expected = rest.split('`').skip(1).step_by(2).collect();
+    Some((field, expected))
+}
+
+/// The message for the unknown `key`, suggesting the closest of `known`.
+fn unknown_key(key: &str, known: &[&str]) -> String {
+    match did_you_mean(key, known) {
+        Some(suggestion) => {
+            format!("unknown key `{}`, did you mean `{}`?", key, suggestion)
+        }
+        None => format!("unknown key `{}`", key),
+    }
+}
+
+/// Returns the closest of `candidates` to `key`, if it is close enough to be a typo.
+fn did_you_mean<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|c| (edit_distance(key, c), *c))
+        .filter(|(dist, _)| *dist <= key.chars().count().max(3) / 3)
+        .min()
+        .map(|(_, c)| c)
+}
+
+/// The edit distance between `a` and `b`, counting swapped neighbours as a single
+/// edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dist[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (dist[i - 1][j] + 1)
+                .min(dist[i][j - 1] + 1)
+                .min(dist[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(dist[i - 2][j - 2] + 1);
+            }
+            dist[i][j] = best;
+        }
+    }
+    dist[a.len()][b.len()]
+}
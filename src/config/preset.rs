@@ -0,0 +1,129 @@
+//! The named sets of settings `preset` selects.
+//!
+//! A preset is the first layer of a config, every key a config file sets overrides
+//! the preset's value for it.
+
+use toml::Table;
+
+/// A named set of settings for a common convention.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// The Cargo.toml conventions of the Rust style guide.
+    CargoStyleGuide,
+    /// taplo's formatter with its own defaults.
+    TaploDefault,
+    /// Only sorting, nothing is reformatted and the top level tables keep their order.
+    MinimalDiff,
+    /// Every rule this tool knows, for manifests that should all look the same.
+    Strict,
+}
+
+/// The default table order and no dependency grouping. The builtin formatter keeps
+/// arrays on one line when they fit, uses double quotes only where needed and turns
+/// `[dependencies.name]` tables into inline tables when they fit.
+const CARGO_STYLE_GUIDE: &str = r#"
+table_order = [
+    "package",
+    "lib",
+    "bin",
+    "features",
+    "dependencies",
+    "build-dependencies",
+    "dev-dependencies",
+]
+formatter = "builtin"
+max_width = 100
+indent_string = "    "
+space_around_eq = true
+compact_arrays = true
+compact_inline_tables = false
+always_trailing_comma = false
+multiline_trailing_comma = true
+quote_style = "double"
+unquote_keys = true
+allowed_blank_lines = 1
+clean_tables = true
+"#;
+
+/// The default table order and no dependency grouping. Formatting is left to taplo
+/// with the values of its own defaults for the options it shares with this config.
+const TAPLO_DEFAULT: &str = r#"
+formatter = "taplo"
+max_width = 80
+indent_string = "  "
+space_around_eq = true
+align_entries = false
+compact_arrays = true
+compact_inline_tables = false
+multiline_trailing_comma = true
+allowed_blank_lines = 2
+"#;
+
+/// No table order, so the top level tables keep their place, and no dependency
+/// grouping. The formatter is off, only the order of keys changes.
+const MINIMAL_DIFF: &str = r#"
+table_order = []
+formatter = "off"
+"#;
+
+/// Every known top level table in a fixed order and the dependencies grouped into
+/// workspace, local and the rest, with headers. The builtin formatter applies every
+/// rule, comments are normalized and aligned and arrays of tables are sorted.
+const STRICT: &str = r#"
+table_order = [
+    "package",
+    "lib",
+    "bin",
+    "example",
+    "test",
+    "bench",
+    "features",
+    "dependencies",
+    "build-dependencies",
+    "dev-dependencies",
+    "target",
+    "patch",
+    "profile",
+    "workspace",
+]
+formatter = "builtin"
+max_width = 100
+indent_string = "    "
+space_around_eq = true
+compact_arrays = true
+compact_inline_tables = false
+always_trailing_comma = false
+multiline_trailing_comma = true
+quote_style = "double"
+unquote_keys = true
+normalize_comments = true
+align_comments = true
+allowed_blank_lines = 1
+clean_tables = true
+sort_array_of_tables = true
+dependency_groups = { workspace = ["@workspace"], local = ["@path", "@git"] }
+dependency_group_headers = true
+"#;
+
+impl Preset {
+    /// The settings of the preset, as they would be written in a `tomlfmt.toml`.
+    pub(crate) fn settings(self) -> Table {
+        let raw = match self {
+            Self::CargoStyleGuide => CARGO_STYLE_GUIDE,
+            Self::TaploDefault => TAPLO_DEFAULT,
+            Self::MinimalDiff => MINIMAL_DIFF,
+            Self::Strict => STRICT,
+        };
+        raw.parse().unwrap()
+    }
+}
@@ -0,0 +1,236 @@
+#![cfg(test)]
+use std::{fs, path::Path};
+
+use clap::ValueEnum;
+use pretty_assertions::assert_eq;
+use toml::Table;
+
+use super::{init::DOCS, validate, validate_metadata, Config, Formatter, Preset};
+use crate::kind::Kind;
+
+#[test]
+fn nested_configs_override_outer() {
+    let config =
+        Config::resolve(Path::new("fixtures/config/member/Cargo.toml"), None, None)
+            .unwrap();
+    // From the workspace metadata
+    assert!(config.crlf);
+    // From the root tomlfmt.toml, over the workspace metadata
+    assert_eq!(config.allowed_blank_lines, 2);
+    assert_eq!(config.table_order, vec!["package", "dependencies"]);
+    // From the member's package metadata
+    assert!(!config.space_around_eq);
+    // From the member's own tomlfmt.toml
+    assert!(config.compact_arrays);
+}
+
+#[test]
+fn workspace_root_ignores_member_config() {
+    let config =
+        Config::resolve(Path::new("fixtures/config/Cargo.toml"), None, None).unwrap();
+    assert!(config.crlf);
+    assert!(config.space_around_eq);
+    assert!(!config.compact_arrays);
+}
+
+#[test]
+fn explicit_config_path() {
+    let config = Config::resolve(
+        Path::new("fixtures/config/member/Cargo.toml"),
+        Some(Path::new("fixtures/config/member/tomlfmt.toml")),
+        None,
+    )
+    .unwrap();
+    assert!(config.compact_arrays);
+    assert!(!config.crlf);
+    assert_eq!(config.allowed_blank_lines, 1);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let path = Path::new("tomlfmt.toml");
+    let err = validate::<Config>("crlf = true\ntable_ordr = []\n", path).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "tomlfmt.toml:2:1: unknown key `table_ordr`, did you mean `table_order`?"
+    );
+
+    let err = validate::<Config>("nonsense = 1\n", path).unwrap_err();
+    assert_eq!(err.to_string(), "tomlfmt.toml:1:1: unknown key `nonsense`");
+
+    let err = validate::<Config>("\ncrlf = \"yes\"\n", path).unwrap_err();
+    assert!(err.to_string().starts_with("tomlfmt.toml:2:8: invalid type"), "{}", err);
+}
+
+#[test]
+fn unknown_metadata_keys_are_rejected() {
+    let path = Path::new("Cargo.toml");
+    let raw = "[package]\nname = \"a\"\n\n[package.metadata.manifmt]\ncrfl = true\n";
+    let err = validate_metadata(raw, "package", path).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Cargo.toml:5:1: unknown key `crfl`, did you mean `crlf`?"
+    );
+    // The other section is not checked
+    validate_metadata(raw, "workspace", path).unwrap();
+
+    let raw = "[workspace]\nmetadata.manifmt.crlf = \"yes\"\n";
+    let err = validate_metadata(raw, "workspace", path).unwrap_err();
+    assert!(err.to_string().starts_with("Cargo.toml:2:25: invalid type"), "{}", err);
+
+    let raw = "[package.metadata.manifmt]\ncrlf = true\n";
+    validate_metadata(raw, "package", path).unwrap();
+}
+
+#[test]
+fn generated_config_documents_every_key() {
+    let config = Config::serde_default();
+    let toml::Value::Table(table) = toml::Value::try_from(&config).unwrap() else {
+        unreachable!()
+    };
+    for key in table.keys() {
+        assert!(DOCS.iter().any(|(k, _)| k == key), "{} is not documented", key);
+    }
+
+    let generated = config.to_commented_toml().unwrap();
+    let parsed: Config = toml::from_str(&generated).unwrap();
+    assert_eq!(toml::to_string(&parsed).unwrap(), toml::to_string(&config).unwrap());
+}
+
+#[test]
+fn generated_config_keeps_every_setting() {
+    let config: Config = toml::from_str(
+        r#"
+preset = "strict"
+workspace_dependency_grouping = "bottom"
+dependency_groups = { "local crates" = ["@path"], async = ["tokio*", "futures*"] }
+table_order = ["package", "dependencies"]
+indent_string = "\t"
+"#,
+    )
+    .unwrap();
+    // The optional keys are only written once they are set
+    let toml::Value::Table(table) = toml::Value::try_from(&config).unwrap() else {
+        unreachable!()
+    };
+    for key in table.keys() {
+        assert!(DOCS.iter().any(|(k, _)| k == key), "{} is not documented", key);
+    }
+
+    let generated = config.to_commented_toml().unwrap();
+    let parsed: Config = toml::from_str(&generated).unwrap();
+    assert_eq!(toml::to_string(&parsed).unwrap(), toml::to_string(&config).unwrap());
+}
+
+#[test]
+fn infer_settings_from_manifests() {
+    let crlf =
+        "[package]\r\nname = \"a\"\r\n\r\n[dependencies]\r\na = [\r\n    \"1\"\r\n]\r\n";
+    let manifests = [
+        crlf.to_string(),
+        crlf.to_string(),
+        fs::read_to_string("fixtures/clippy.toml").unwrap(),
+    ];
+    let mut config = Config::serde_default();
+    config.infer_from(&manifests);
+    assert!(config.crlf);
+    assert!(!config.multiline_trailing_comma);
+    let pos = |t: &str| config.table_order.iter().position(|o| o == t).unwrap();
+    assert_eq!(pos("package"), 0);
+    assert!(pos("dependencies") < pos("dev-dependencies"));
+}
+
+#[test]
+fn dependency_groups_keep_their_order() {
+    let input = "[dependency_groups]\nzeta = [\"z*\"]\nalpha = [\"@path\", \"/^a/\"]\n";
+    let config: Config = toml::from_str(input).unwrap();
+    // Config files are merged as tables first, which only keep their order with the
+    // `preserve_order` feature of `toml`
+    let table = toml::from_str(input).unwrap();
+    let Ok(merged) = Config::from_table(table, Path::new("tomlfmt.toml"), None) else {
+        panic!("the groups are valid");
+    };
+    assert_eq!(merged.dependency_groups, config.dependency_groups);
+    assert_eq!(
+        config.dependency_groups.0,
+        vec![
+            ("zeta".to_owned(), vec!["z*".to_owned()]),
+            ("alpha".to_owned(), vec!["@path".to_owned(), "/^a/".to_owned()]),
+        ]
+    );
+
+    let table = toml::from_str("[dependency_groups]\nodd = [\"@vendored\"]\n").unwrap();
+    let Err(err) = Config::from_table(table, Path::new("tomlfmt.toml"), None) else {
+        panic!("`@vendored` is not an attribute");
+    };
+    assert_eq!(
+        err.to_string(),
+        "invalid config for tomlfmt.toml: bad pattern `@vendored` in dependency_groups: \
+         unknown attribute `@vendored`, expected `@path`, `@git` or `@workspace`"
+    );
+}
+
+#[test]
+fn keys_override_the_preset() {
+    let table = toml::from_str("preset = \"strict\"\nmax_width = 80\n").unwrap();
+    let Ok(config) = Config::from_table(table, Path::new("tomlfmt.toml"), None) else {
+        panic!("`strict` is a preset");
+    };
+    assert!(config.clean_tables);
+    assert_eq!(config.max_width, 80);
+    assert_eq!(config.preset, Some(Preset::Strict));
+
+    let table = toml::from_str("preset = \"strict\"\nmax_width = 80\n").unwrap();
+    let path = Path::new("tomlfmt.toml");
+    let Ok(config) = Config::from_table(table, path, Some(Preset::MinimalDiff)) else {
+        panic!("`minimal-diff` is a preset");
+    };
+    assert_eq!(config.formatter, Formatter::Off);
+    assert!(!config.clean_tables);
+    assert_eq!(config.max_width, 80);
+
+    let table = toml::from_str("preset = \"tidy\"\n").unwrap();
+    let Err(err) = Config::from_table(table, Path::new("tomlfmt.toml"), None) else {
+        panic!("`tidy` is not a preset");
+    };
+    assert!(
+        err.to_string()
+            .starts_with("invalid config for tomlfmt.toml: unknown variant `tidy`")
+    );
+}
+
+#[test]
+fn presets_match_their_fixtures() {
+    let path = Path::new("fixtures/presets/Cargo.toml");
+    let input = fs::read_to_string(path).unwrap();
+    for preset in Preset::value_variants() {
+        let name = preset.to_possible_value().unwrap().get_name().to_owned();
+        let config = Config::from_table(Table::new(), path, Some(*preset)).unwrap();
+        let sorted = crate::sort_manifest(path, &input, &config, Kind::Cargo).unwrap();
+        let fixture =
+            fs::read_to_string(format!("fixtures/presets/{}.toml", name)).unwrap();
+        assert_eq!(sorted, fixture, "{}", name);
+        crate::verify::ensure_equivalent(&input, &sorted, Kind::Cargo, &config).unwrap();
+        let again = crate::sort_manifest(path, &sorted, &config, Kind::Cargo).unwrap();
+        assert_eq!(again, sorted, "{} is not idempotent", name);
+    }
+}
+
+#[test]
+fn formatting_is_opt_in() {
+    for entry in fs::read_dir("fixtures").unwrap() {
+        let path = entry.unwrap().path();
+        if !path.is_file() {
+            continue;
+        }
+        let input = fs::read_to_string(&path).unwrap();
+        let config = Config::new();
+        let sorted = crate::sort_manifest(&path, &input, &config, Kind::Cargo).unwrap();
+        let order = Kind::Cargo.table_order(&config);
+        let only_sorted =
+            crate::sort::sort_toml(&input, Kind::Cargo.matcher(), false, &order, &config)
+                .unwrap()
+                .to_string();
+        assert_eq!(sorted, only_sorted, "{}", path.display());
+    }
+}
@@ -0,0 +1,39 @@
+//! Helpers for the decor of keys and values shared by sorting and formatting.
+
+use toml_edit::{Decor, InlineTable, Item, RawString, Table, TableLike, Value};
+
+/// The text of `raw`, empty when there is none or it points into the input.
+pub(crate) fn raw(raw: Option<&RawString>) -> &str {
+    raw.and_then(RawString::as_str).unwrap_or_default()
+}
+
+/// Whether `item` is written as `key = value` lines, dotted keys included.
+pub(crate) fn is_key_value(item: &Item) -> bool {
+    item.is_value() || item.as_table().is_some_and(Table::is_dotted)
+}
+
+/// Whether there is a comment in the prefix or suffix of `decor`.
+pub(crate) fn has_comment(decor: &Decor) -> bool {
+    [decor.prefix(), decor.suffix()].iter().flatten().any(|raw| has_comment_raw(raw))
+}
+
+pub(crate) fn has_comment_raw(raw: &RawString) -> bool {
+    raw.as_str().is_some_and(|s| s.contains('#'))
+}
+
+/// The value written last in `table`, looking into dotted keys.
+pub(crate) fn last_value_mut(table: &mut dyn TableLike) -> Option<&mut Value> {
+    let items = table.iter_mut().map(|(_, item)| item).collect::<Vec<_>>();
+    for item in items.into_iter().rev() {
+        let dotted = item.as_table().is_some_and(Table::is_dotted)
+            || item.as_inline_table().is_some_and(InlineTable::is_dotted);
+        if !dotted {
+            if let Item::Value(val) = item {
+                return Some(val);
+            }
+        } else if let Some(val) = item.as_table_like_mut().and_then(last_value_mut) {
+            return Some(val);
+        }
+    }
+    None
+}
@@ -0,0 +1,277 @@
+//! The spacing of comments and the column of trailing comments.
+//!
+//! With `normalize_comments` every comment gets a single space after its `#`, comments
+//! starting with `#!` or `##` are left alone. With `align_comments` the trailing
+//! comments of consecutive key value lines start at the same column, and a trailing
+//! comment that would go past `max_width` is moved above its line instead.
+
+use toml_edit::{Decor, Document, InlineTable, Item, KeyMut, RawString, Table, Value};
+
+use crate::{
+    decor::{is_key_value, raw},
+    sort::{visit_headers, Directives},
+    Config,
+};
+
+/// Normalizes and aligns the comments of `toml`, the keys and tables a directive
+/// covers are left as written.
+pub(super) fn tidy(toml: &mut Document, config: &Config, directives: &Directives) {
+    if !config.normalize_comments && !config.align_comments {
+        return;
+    }
+    let comments = Comments { config };
+    if !directives.is_table_skipped("") {
+        comments.table("", toml.as_table_mut(), directives);
+    }
+    visit_headers(toml.as_table_mut(), &mut |path, table| {
+        if directives.is_table_skipped(path) {
+            return;
+        }
+        if !directives.is_table_pinned(path) {
+            comments.decor(table.decor_mut());
+        }
+        comments.table(path, table, directives);
+    });
+    let trailing = toml.trailing().as_str().unwrap_or_default();
+    let trailing = comments.normalize(trailing);
+    toml.set_trailing(trailing);
+}
+
+/// A key value line of a table as far as its trailing comment is concerned.
+struct Line {
+    /// Whether a blank line or a comment comes between this line and the one above.
+    separated: bool,
+    /// The column the line ends at before its comment, `None` for a value spanning
+    /// lines or a key left as written.
+    end: Option<usize>,
+    /// The trailing comment, from its `#` on.
+    comment: Option<String>,
+    /// The lines of the suffix of the value after the one of its trailing comment.
+    rest: String,
+}
+
+struct Comments<'a> {
+    config: &'a Config,
+}
+
+impl Comments<'_> {
+    fn table(&self, path: &str, table: &mut Table, directives: &Directives) {
+        let mut lines = vec![];
+        for (mut key, item) in table.iter_mut() {
+            if !is_key_value(item) {
+                continue;
+            }
+            let pinned = directives.is_key_pinned(path, key.get());
+            if !pinned {
+                self.decor(key.decor_mut());
+                if let Some(value) = leaf(item, &mut vec![]) {
+                    self.value(value);
+                }
+            }
+            lines.push(line(&key, item, pinned));
+        }
+        if !self.config.align_comments {
+            return;
+        }
+
+        let suffixes = self.align(&lines);
+        let keys = table.iter_mut().filter(|(_, item)| is_key_value(item));
+        for ((mut key, item), suffix) in keys.zip(suffixes) {
+            let Some((comment, suffix)) = suffix else {
+                continue;
+            };
+            if let Some(value) = leaf(item, &mut vec![]) {
+                value.decor_mut().set_suffix(suffix);
+            }
+            // A comment that does not fit after its line goes above it
+            if let Some(comment) = comment {
+                let decor = key.decor_mut();
+                let prefix =
+                    decor.prefix().and_then(RawString::as_str).unwrap_or_default();
+                let indent = prefix.rsplit('\n').next().unwrap_or_default();
+                let above = &prefix[..prefix.len() - indent.len()];
+                let newline = if prefix.contains("\r\n") { "\r\n" } else { "\n" };
+                let prefix =
+                    format!("{}{}{}{}{}", above, indent, comment, newline, indent);
+                decor.set_prefix(prefix);
+            }
+        }
+    }
+
+    /// The new suffix of every line whose trailing comment moves, along with the
+    /// comment when it moves above the line.
+    fn align(&self, lines: &[Line]) -> Vec<Option<(Option<String>, String)>> {
+        let mut suffixes = vec![None; lines.len()];
+        let fits = |line: &Line| match (line.end, &line.comment) {
+            (Some(end), Some(comment)) => {
+                end + 1 + comment.chars().count() <= self.max_width()
+            }
+            _ => true,
+        };
+
+        let mut start = 0;
+        while start < lines.len() {
+            let mut end = start + 1;
+            while end < lines.len()
+                && !lines[end].separated
+                && lines[end - 1].end.is_some()
+            {
+                end += 1;
+            }
+            let block = &lines[start..end];
+            let column = block
+                .iter()
+                .filter(|line| line.comment.is_some() && fits(line))
+                .flat_map(|line| line.end)
+                .max();
+            for (idx, line) in block.iter().enumerate() {
+                let (Some(line_end), Some(comment), Some(column)) =
+                    (line.end, &line.comment, column.or(line.end))
+                else {
+                    continue;
+                };
+                let rest = &line.rest;
+                suffixes[start + idx] = Some(if fits(line) {
+                    let pad = " ".repeat(column + 1 - line_end);
+                    (None, format!("{}{}{}", pad, comment, rest))
+                } else {
+                    (Some(comment.clone()), rest.to_owned())
+                });
+            }
+            start = end;
+        }
+        suffixes
+    }
+
+    /// Normalizes the comments inside of a value and after it.
+    fn value(&self, value: &mut Value) {
+        self.decor(value.decor_mut());
+        match value {
+            Value::Array(arr) => {
+                arr.iter_mut().for_each(|v| self.value(v));
+                let trailing =
+                    self.normalize(arr.trailing().as_str().unwrap_or_default());
+                arr.set_trailing(trailing);
+            }
+            Value::InlineTable(table) => {
+                table.iter_mut().for_each(|(_, v)| self.value(v))
+            }
+            _ => {}
+        }
+    }
+
+    fn decor(&self, decor: &mut Decor) {
+        let prefix =
+            decor.prefix().and_then(RawString::as_str).map(|p| self.normalize(p));
+        let suffix =
+            decor.suffix().and_then(RawString::as_str).map(|s| self.normalize(s));
+        if let Some(prefix) = prefix {
+            decor.set_prefix(prefix);
+        }
+        if let Some(suffix) = suffix {
+            decor.set_suffix(suffix);
+        }
+    }
+
+    /// Puts a single space after the `#` of every comment in `raw`.
+    fn normalize(&self, raw: &str) -> String {
+        if !self.config.normalize_comments {
+            return raw.to_owned();
+        }
+        raw.split_inclusive('\n')
+            .map(|line| {
+                let Some(hash) = line.find('#') else {
+                    return line.to_owned();
+                };
+                let (before, comment) = line.split_at(hash);
+                let text = &comment[1..];
+                let content = text.trim_start_matches([' ', '\t']);
+                if text.starts_with(['!', '#']) || content.trim().is_empty() {
+                    line.to_owned()
+                } else {
+                    format!("{}# {}", before, content)
+                }
+            })
+            .collect()
+    }
+
+    fn max_width(&self) -> usize { self.config.max_width }
+}
+
+/// Describes the line of `key`, the values of pinned keys never count as ending at a
+/// known column.
+fn line(key: &KeyMut<'_>, item: &mut Item, pinned: bool) -> Line {
+    let prefix = raw(key.decor().prefix());
+    let separated = prefix.contains('\n');
+    let indent = prefix.rsplit('\n').next().unwrap_or_default();
+    // The prefix of the key is above its line, only the indentation counts
+    let suffix = key.decor().suffix().and_then(RawString::as_str).map(ToOwned::to_owned);
+    let mut path = vec![(None, key.display_repr().into_owned(), suffix)];
+    let Some(value) = leaf(item, &mut path) else {
+        return Line { separated, end: None, comment: None, rest: String::new() };
+    };
+    let suffix = raw(value.decor().suffix());
+    let (first, rest) = suffix.split_at(suffix.find('\n').unwrap_or(suffix.len()));
+    let comment = first.find('#').map(|hash| first[hash..].trim_end().to_owned());
+
+    // The parts of a key path are joined by dots, the last one is followed by a space
+    // unless its decor says otherwise
+    let last = path.len() - 1;
+    let key_text = path
+        .into_iter()
+        .enumerate()
+        .map(|(idx, (prefix, repr, suffix))| {
+            let default = if idx == last { " " } else { "" };
+            let suffix = suffix.as_deref().unwrap_or(default);
+            format!("{}{}{}", prefix.unwrap_or_default(), repr, suffix)
+        })
+        .collect::<Vec<_>>()
+        .join(".");
+    let mut bare = value.clone();
+    bare.decor_mut().set_suffix("");
+    if bare.decor().prefix().is_none() {
+        bare.decor_mut().set_prefix(" ");
+    }
+    let text = format!("{}{}={}", indent, key_text, bare);
+    let end = (!pinned && !text.contains('\n')).then(|| text.chars().count());
+    Line { separated, end, comment, rest: rest.to_owned() }
+}
+
+/// The prefix, text and suffix of one part of a key path.
+type KeyPart = (Option<String>, String, Option<String>);
+
+fn key_part(decor: &Decor, repr: &str) -> KeyPart {
+    let owned =
+        |r: Option<&RawString>| r.and_then(RawString::as_str).map(ToOwned::to_owned);
+    (owned(decor.prefix()), repr.to_owned(), owned(decor.suffix()))
+}
+
+/// The one value `item` holds, adding the keys of the dotted tables on the way to
+/// `path`. `None` for dotted keys holding more than one value.
+fn leaf<'a>(item: &'a mut Item, path: &mut Vec<KeyPart>) -> Option<&'a mut Value> {
+    match item {
+        Item::Value(value) => leaf_value(value, path),
+        Item::Table(dotted) if dotted.is_dotted() && dotted.len() == 1 => {
+            let (key, item) = dotted.iter_mut().next()?;
+            path.push(key_part(key.decor(), &key.display_repr()));
+            leaf(item, path)
+        }
+        _ => None,
+    }
+}
+
+fn leaf_value<'a>(
+    value: &'a mut Value,
+    path: &mut Vec<KeyPart>,
+) -> Option<&'a mut Value> {
+    if !value.as_inline_table().is_some_and(InlineTable::is_dotted) {
+        return Some(value);
+    }
+    let dotted = value.as_inline_table_mut()?;
+    if dotted.len() != 1 {
+        return None;
+    }
+    let (key, value) = dotted.iter_mut().next()?;
+    path.push(key_part(key.decor(), &key.display_repr()));
+    leaf_value(value, path)
+}
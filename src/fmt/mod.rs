@@ -0,0 +1,432 @@
+//! Formatting a sorted document.
+//!
+//! Arrays and inline tables are laid out by `max_width`: a value that fits on the line
+//! after its key is written on one line, one that does not is exploded with one element
+//! per line. An inline table can not span lines so only the arrays in it are exploded.
+//! Values holding comments keep the layout they have. With `align_entries` the keys of
+//! consecutive lines are padded so their `=` line up.
+//! Before that `quotes` rewrites the quotes of keys and strings and `whitespace`
+//! tidies the blank lines and whitespace between the lines, after it `comments` spaces
+//! and aligns the comments.
+//!
+//! With `formatter = "taplo"` the sorted text goes through taplo's formatter instead,
+//! the entries and headers the directives cover are then put back as they were.
+
+use std::ops::Range;
+
+use taplo::syntax::SyntaxKind;
+use toml_edit::{Array, Document, InlineTable, Item, KeyMut, Table, Value};
+
+use crate::{
+    decor::{has_comment, has_comment_raw, last_value_mut},
+    sort::{visit_headers, Directive, Directives},
+    Config,
+};
+
+mod comments;
+mod quotes;
+mod test;
+mod whitespace;
+
+pub use self::quotes::normalize_quotes;
+
+/// Formats `toml` following `config`, the keys and tables a `# manifmt: skip` or
+/// `# manifmt: off` directive covers are left as written.
+pub fn fmt_toml(toml: &mut Document, config: &Config) {
+    normalize_quotes(toml, config);
+    let directives = Directives::find(toml);
+    whitespace::tidy(toml, config, &directives);
+    let fmt = Formatter { config };
+    if !directives.is_table_skipped("") {
+        fmt.table("", toml.as_table_mut(), &directives);
+    }
+    visit_headers(toml.as_table_mut(), &mut |path, table| {
+        if !directives.is_table_skipped(path) {
+            fmt.table(path, table, &directives);
+        }
+    });
+    comments::tidy(toml, config, &directives);
+}
+
+/// Formats `toml` with taplo, the options taplo shares with `config` are taken from
+/// it and the rest keep taplo's defaults. taplo never reorders anything here, the
+/// keys and tables a directive covers are put back as written afterwards.
+pub fn taplo_fmt(toml: &str, config: &Config) -> String {
+    let options = taplo::formatter::Options {
+        array_trailing_comma: config.multiline_trailing_comma,
+        compact_arrays: config.compact_arrays,
+        compact_inline_tables: config.compact_inline_tables,
+        compact_entries: !config.space_around_eq,
+        align_entries: config.align_entries,
+        column_width: config.max_width,
+        indent_string: config.indent_string.clone(),
+        trailing_newline: config.trailing_newline,
+        reorder_keys: false,
+        reorder_arrays: false,
+        allowed_blank_lines: config.allowed_blank_lines,
+        crlf: config.crlf,
+        ..Default::default()
+    };
+    let formatted = taplo::formatter::format(toml, options);
+    keep_pinned(toml, &formatted)
+}
+
+/// Puts the entries and table headers of `formatted` a directive covers back the way
+/// `raw` writes them. taplo neither adds, drops nor moves any of them so they are
+/// matched up by their order, `formatted` is kept as is when they do not match.
+fn keep_pinned(raw: &str, formatted: &str) -> String {
+    let before = taplo_items(raw);
+    let after = taplo_items(formatted);
+    if before.len() != after.len() || !before.iter().any(|(_, pinned)| *pinned) {
+        return formatted.to_owned();
+    }
+    let mut out = String::new();
+    let mut end = 0;
+    for ((range, pinned), (new_range, _)) in before.into_iter().zip(after) {
+        if pinned {
+            out.push_str(&formatted[end..new_range.start]);
+            out.push_str(&raw[range]);
+            end = new_range.end;
+        }
+    }
+    out.push_str(&formatted[end..]);
+    out
+}
+
+/// The ranges of the entries and table headers of `toml` in the order they are
+/// written, with whether a `# manifmt:` directive covers them.
+fn taplo_items(toml: &str) -> Vec<(Range<usize>, bool)> {
+    let mut items = vec![];
+    let (mut off, mut skip, mut table_skipped) = (false, false, false);
+    for child in taplo::parser::parse(toml).into_syntax().children_with_tokens() {
+        let range = child.text_range();
+        let range = usize::from(range.start())..usize::from(range.end());
+        match child.kind() {
+            SyntaxKind::COMMENT => match Directive::parse(&toml[range.clone()]) {
+                Some(Directive::Off) => off = true,
+                Some(Directive::On) => off = false,
+                Some(Directive::Skip) => skip = true,
+                None => {}
+            },
+            // A blank line sets a `skip` apart from the item below it
+            SyntaxKind::NEWLINE if toml[range.clone()].matches('\n').count() > 1 => {
+                skip = false
+            }
+            SyntaxKind::TABLE_HEADER | SyntaxKind::TABLE_ARRAY_HEADER => {
+                table_skipped = skip;
+                items.push((range, off || skip));
+                skip = false;
+            }
+            SyntaxKind::ENTRY => {
+                // The comment after the value is the last token of the value
+                let value = child.as_node().and_then(|entry| {
+                    entry.children().find(|node| node.kind() == SyntaxKind::VALUE)
+                });
+                let skip_after = value.is_some_and(|value| {
+                    value.children_with_tokens().any(|token| {
+                        token.kind() == SyntaxKind::COMMENT
+                            && Directive::parse(&token.to_string())
+                                == Some(Directive::Skip)
+                    })
+                });
+                items.push((range, off || skip || skip_after || table_skipped));
+                skip = false;
+            }
+            _ => {}
+        }
+    }
+    items
+}
+
+struct Formatter<'a> {
+    config: &'a Config,
+}
+
+impl Formatter<'_> {
+    /// Formats the key/value lines of the table at the dotted `path`.
+    fn table(&self, path: &str, table: &mut Table, directives: &Directives) {
+        let pads = if self.config.align_entries {
+            self.pads(path, table, directives)
+        } else {
+            vec![0; table.len()]
+        };
+        for ((mut key, item), pad) in table.iter_mut().zip(pads) {
+            if directives.is_key_pinned(path, key.get()) {
+                continue;
+            }
+            let indent = indent_of(&key).to_owned();
+            let col = indent.chars().count() + width_of(&key);
+            match item {
+                Item::Value(value) => self.entry(&mut key, value, col, &indent, pad),
+                Item::Table(dotted) if dotted.is_dotted() => {
+                    self.dotted_table(dotted, col + 1, &indent, pad)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The spaces every item of `table` gets after its key so the `=` of consecutive
+    /// key/value lines line up. A blank line, a comment or a key left as written ends a
+    /// block, and so does a dotted key holding more than one value.
+    fn pads(&self, path: &str, table: &Table, directives: &Directives) -> Vec<usize> {
+        let widths = table
+            .iter()
+            .map(|(key, item)| {
+                let (key, _) = table.get_key_value(key)?;
+                let prefix =
+                    key.decor().prefix().and_then(|p| p.as_str()).unwrap_or_default();
+                if directives.is_key_pinned(path, key.get()) {
+                    return None;
+                }
+                let indent = prefix.rsplit('\n').next().unwrap_or_default();
+                let width = indent.chars().count() + key.display_repr().chars().count();
+                Some((prefix.contains('\n'), width + path_width(item)?))
+            })
+            .collect::<Vec<_>>();
+
+        let mut pads = vec![0; widths.len()];
+        let mut start = 0;
+        while start < widths.len() {
+            let mut end = start + 1;
+            while end < widths.len()
+                && widths[end - 1].is_some()
+                && widths[end].is_some_and(|(separated, _)| !separated)
+            {
+                end += 1;
+            }
+            let block = start..end;
+            let column = widths[block.clone()].iter().flatten().map(|(_, w)| *w).max();
+            for idx in block {
+                if let (Some((_, width)), Some(column)) = (widths[idx], column) {
+                    pads[idx] = column - width;
+                }
+            }
+            start = end;
+        }
+        pads
+    }
+
+    /// Formats the keys of a dotted table, the key path up to it ends at `col`. `pad`
+    /// goes after the last key when the table holds a single value.
+    fn dotted_table(&self, table: &mut Table, col: usize, indent: &str, pad: usize) {
+        let pad = if table.len() == 1 { pad } else { 0 };
+        for (mut key, item) in table.iter_mut() {
+            let col = col + width_of(&key);
+            match item {
+                Item::Value(value) => self.entry(&mut key, value, col, indent, pad),
+                Item::Table(dotted) if dotted.is_dotted() => {
+                    self.dotted_table(dotted, col + 1, indent, pad)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Formats the `=` after `key`, padded by `pad` spaces, and lays out its value, the
+    /// key ends at `col`.
+    fn entry(
+        &self,
+        key: &mut KeyMut<'_>,
+        value: &mut Value,
+        col: usize,
+        indent: &str,
+        pad: usize,
+    ) {
+        if let Value::InlineTable(dotted) = value {
+            if dotted.is_dotted() {
+                let pad = if dotted.len() == 1 { pad } else { 0 };
+                for (mut key, value) in dotted.iter_mut() {
+                    let col = col + 1 + width_of(&key);
+                    self.entry(&mut key, value, col, indent, pad);
+                }
+                return;
+            }
+        }
+        let eq = self.eq();
+        key.decor_mut().set_suffix(format!("{}{}", " ".repeat(pad), eq));
+        value.decor_mut().set_prefix(eq);
+        self.value(value, col + pad + 1 + 2 * eq.len(), indent);
+    }
+
+    /// Lays out `value` that starts at column `col` of a line indented by `indent`.
+    fn value(&self, value: &mut Value, col: usize, indent: &str) {
+        match value {
+            Value::Array(arr) => self.array(arr, col, indent),
+            Value::InlineTable(table) => self.inline_table(table, col, indent),
+            _ => {}
+        }
+    }
+
+    fn array(&self, arr: &mut Array, col: usize, indent: &str) {
+        if arr.iter().any(holds_comment) || has_comment_raw(arr.trailing()) {
+            return;
+        }
+        let mut one_line = arr.clone();
+        self.one_line_array(&mut one_line);
+        if one_line.is_empty()
+            || col + width(&one_line.clone().into()) <= self.max_width()
+        {
+            *arr = one_line;
+        } else {
+            self.explode(arr, indent);
+        }
+    }
+
+    fn inline_table(&self, table: &mut InlineTable, col: usize, indent: &str) {
+        if table.iter().any(|(_, v)| holds_comment(v)) {
+            return;
+        }
+        self.one_line_table(table);
+        if col + width(&table.clone().into()) <= self.max_width() {
+            return;
+        }
+        // Only the arrays of an inline table may span lines
+        for (_, value) in table.iter_mut() {
+            if let Value::Array(arr) = value {
+                if !arr.is_empty() {
+                    self.explode(arr, indent);
+                }
+            }
+        }
+    }
+
+    /// Writes every element of `arr` on a line of its own, one level deeper than
+    /// `indent`.
+    fn explode(&self, arr: &mut Array, indent: &str) {
+        let inner = format!("{}{}", indent, self.config.indent_string);
+        let col = inner.chars().count();
+        for value in arr.iter_mut() {
+            value.decor_mut().clear();
+            value.decor_mut().set_prefix(format!("\n{}", inner));
+            self.value(value, col, &inner);
+        }
+        arr.set_trailing_comma(self.config.multiline_trailing_comma);
+        arr.set_trailing(format!("\n{}", indent));
+    }
+
+    fn one_line_value(&self, value: &mut Value) {
+        match value {
+            Value::Array(arr) => self.one_line_array(arr),
+            Value::InlineTable(table) => self.one_line_table(table),
+            _ => {}
+        }
+    }
+
+    /// Writes `arr` on one line, keeping the padding inside the brackets it has
+    /// unless `compact_arrays` is set.
+    fn one_line_array(&self, arr: &mut Array) {
+        let padded = !arr.to_string().contains('\n')
+            && arr
+                .iter()
+                .next()
+                .and_then(|v| v.decor().prefix())
+                .and_then(|p| p.as_str())
+                == Some(" ");
+        let pad = if padded && !self.config.compact_arrays { " " } else { "" };
+        for (idx, value) in arr.iter_mut().enumerate() {
+            self.one_line_value(value);
+            value.decor_mut().clear();
+            if idx == 0 {
+                value.decor_mut().set_prefix(pad);
+            }
+        }
+        let comma = self.config.always_trailing_comma && !arr.is_empty();
+        arr.set_trailing_comma(comma);
+        arr.set_trailing(if arr.is_empty() { "" } else { pad });
+    }
+
+    /// Writes `table` as `{ key = value }`, or `{key = value}` with
+    /// `compact_inline_tables`.
+    fn one_line_table(&self, table: &mut InlineTable) {
+        table.set_preamble("");
+        self.one_line_entries(table);
+        if !self.config.compact_inline_tables {
+            return;
+        }
+        // A dotted table shares its key between its values so it keeps its padding
+        let first = table.iter_mut().next();
+        if let Some((mut key, value)) = first {
+            if !value.as_inline_table().is_some_and(|t| t.is_dotted() && t.len() > 1) {
+                key.decor_mut().set_prefix("");
+            }
+        }
+        if let Some(last) = last_value_mut(table) {
+            last.decor_mut().set_suffix("");
+        }
+    }
+
+    fn one_line_entries(&self, table: &mut InlineTable) {
+        let eq = self.eq();
+        for (mut key, value) in table.iter_mut() {
+            key.decor_mut().clear();
+            match value {
+                Value::InlineTable(dotted) if dotted.is_dotted() => {
+                    self.one_line_entries(dotted)
+                }
+                _ => {
+                    self.one_line_value(value);
+                    value.decor_mut().clear();
+                    value.decor_mut().set_prefix(eq);
+                    key.decor_mut().set_suffix(eq);
+                }
+            }
+        }
+    }
+
+    fn eq(&self) -> &'static str { if self.config.space_around_eq { " " } else { "" } }
+
+    fn max_width(&self) -> usize { self.config.max_width }
+}
+
+/// Whether there is a comment inside `value` or around it.
+fn holds_comment(value: &Value) -> bool {
+    has_comment(value.decor())
+        || match value {
+            Value::Array(arr) => {
+                arr.iter().any(holds_comment) || has_comment_raw(arr.trailing())
+            }
+            Value::InlineTable(table) => table.iter().any(|(_, v)| holds_comment(v)),
+            _ => false,
+        }
+}
+
+/// The number of characters `value` takes up without its decor.
+fn width(value: &Value) -> usize {
+    let mut value = value.clone();
+    value.decor_mut().clear();
+    value.to_string().chars().count()
+}
+
+fn width_of(key: &KeyMut<'_>) -> usize { key.display_repr().chars().count() }
+
+fn indent_of<'k>(key: &'k KeyMut<'_>) -> &'k str {
+    let prefix = key.decor().prefix().and_then(|p| p.as_str()).unwrap_or_default();
+    prefix.rsplit('\n').next().unwrap_or_default()
+}
+
+/// The width of the rest of the key path `item` is written with, `None` when it holds
+/// more than one value.
+fn path_width(item: &Item) -> Option<usize> {
+    match item {
+        Item::Value(value) => value_path_width(value),
+        Item::Table(dotted) if dotted.is_dotted() && dotted.len() == 1 => {
+            let (key, item) = dotted.iter().next()?;
+            let (key, _) = dotted.get_key_value(key)?;
+            Some(1 + key.display_repr().chars().count() + path_width(item)?)
+        }
+        _ => None,
+    }
+}
+
+fn value_path_width(value: &Value) -> Option<usize> {
+    let Some(dotted) = value.as_inline_table().filter(|t| t.is_dotted()) else {
+        return Some(0);
+    };
+    if dotted.len() != 1 {
+        return None;
+    }
+    let (key, value) = dotted.iter().next()?;
+    let (key, _) = dotted.get_key_value(key)?;
+    Some(1 + key.display_repr().chars().count() + value_path_width(value)?)
+}
@@ -0,0 +1,165 @@
+//! Rewriting the quotes of keys and strings, see `quote_style` and `unquote_keys`.
+//!
+//! Only single-line strings are rewritten, and a string is only ever written in a way
+//! that parses back to the same text.
+
+use std::fmt::Write;
+
+use toml_edit::{Document, InlineTable, Item, KeyMut, Table, Value};
+
+use crate::{config::QuoteStyle, sort::Directives, Config};
+
+/// Rewrites the keys and strings of `toml` following `quote_style` and `unquote_keys`,
+/// the keys and tables a directive covers are left as written.
+pub fn normalize_quotes(toml: &mut Document, config: &Config) {
+    if config.quote_style == QuoteStyle::Preserve && !config.unquote_keys {
+        return;
+    }
+    let directives = Directives::find(toml);
+    let quotes = Quotes { config, directives: &directives };
+    if !directives.is_table_skipped("") {
+        quotes.table(&mut String::new(), toml.as_table_mut());
+    }
+}
+
+struct Quotes<'a> {
+    config: &'a Config,
+    directives: &'a Directives,
+}
+
+impl Quotes<'_> {
+    fn table(&self, path: &mut String, table: &mut Table) {
+        for (mut key, item) in table.iter_mut() {
+            let len = path.len();
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(key.get());
+            let header = matches!(item, Item::Table(t) if !t.is_dotted())
+                || item.is_array_of_tables();
+            let parent = &path[..len];
+            let skipped = if header {
+                self.directives.is_table_skipped(path)
+            } else {
+                self.directives.is_key_pinned(parent, key.get())
+            };
+            if !skipped {
+                self.key(&mut key);
+                match item {
+                    Item::Value(value) => self.value(value),
+                    Item::Table(dotted) if dotted.is_dotted() => {
+                        self.dotted_table(dotted)
+                    }
+                    Item::Table(table) => self.table(path, table),
+                    Item::ArrayOfTables(arr) => {
+                        for table in arr.iter_mut() {
+                            self.table(path, table);
+                        }
+                    }
+                    Item::None => {}
+                }
+            }
+            path.truncate(len);
+        }
+    }
+
+    fn dotted_table(&self, table: &mut Table) {
+        for (mut key, item) in table.iter_mut() {
+            self.key(&mut key);
+            match item {
+                Item::Value(value) => self.value(value),
+                Item::Table(dotted) => self.dotted_table(dotted),
+                _ => {}
+            }
+        }
+    }
+
+    fn inline_table(&self, table: &mut InlineTable) {
+        for (mut key, value) in table.iter_mut() {
+            self.key(&mut key);
+            self.value(value);
+        }
+    }
+
+    fn value(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => {
+                let raw = s.as_repr().and_then(|r| r.as_raw().as_str());
+                let Some(raw) = raw.and_then(|raw| requote(raw, s.value(), self.config))
+                else {
+                    return;
+                };
+                let Ok(Value::String(mut requoted)) = raw.parse::<Value>() else {
+                    return;
+                };
+                if requoted.value() == s.value() {
+                    *requoted.decor_mut() = s.decor().clone();
+                    *s = requoted;
+                }
+            }
+            Value::Array(arr) => arr.iter_mut().for_each(|v| self.value(v)),
+            Value::InlineTable(table) => self.inline_table(table),
+            _ => {}
+        }
+    }
+
+    /// Drops the quotes of a key that does not need them.
+    fn key(&self, key: &mut KeyMut<'_>) {
+        let quoted = key.display_repr().starts_with(['"', '\'']);
+        if !self.config.unquote_keys || !quoted || !is_bare(key.get()) {
+            return;
+        }
+        let decor = key.decor().clone();
+        key.fmt();
+        *key.decor_mut() = decor;
+    }
+}
+
+/// The new text of the string `value` written as `raw`, or `None` to keep it.
+fn requote(raw: &str, value: &str, config: &Config) -> Option<String> {
+    if raw.starts_with("\"\"\"") || raw.starts_with("'''") {
+        return None;
+    }
+    let literal = raw.starts_with('\'');
+    let needs_escapes = value.chars().any(|c| c == '"' || c == '\\' || c.is_control());
+    let requoted = match config.quote_style {
+        QuoteStyle::Preserve => return None,
+        // A literal string is kept when it would need escapes as a basic one
+        QuoteStyle::Double if literal && needs_escapes => return None,
+        QuoteStyle::Double => basic(value),
+        QuoteStyle::Single if literal => return None,
+        QuoteStyle::Single if can_be_literal(value) => format!("'{}'", value),
+        QuoteStyle::Single => basic(value),
+    };
+    (requoted != raw).then_some(requoted)
+}
+
+/// `value` as a basic string, escaping only what has to be.
+fn basic(value: &str) -> String {
+    let mut out = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u{c}' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => write!(out, "\\u{:04X}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Literal strings can not hold a `'` or control characters other than tab.
+fn can_be_literal(value: &str) -> bool {
+    !value.chars().any(|c| c == '\'' || (c.is_control() && c != '\t'))
+}
+
+fn is_bare(key: &str) -> bool {
+    !key.is_empty()
+        && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
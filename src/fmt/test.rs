@@ -0,0 +1,358 @@
+#![cfg(test)]
+use pretty_assertions::assert_eq;
+use toml_edit::Document;
+
+use crate::{config::QuoteStyle, Config};
+
+fn fmt(input: &str, config: &Config) -> String {
+    let mut toml = input.parse::<Document>().unwrap();
+    super::fmt_toml(&mut toml, config);
+    toml.to_string()
+}
+
+#[test]
+fn wraps_at_max_width() {
+    let input = r#"[features]
+default = ["std", "derive", "serde", "rayon", "tokio", "macros", "unstable", "simd"]
+small = [
+    "std",
+    "derive"
+]
+
+[dependencies]
+serde = {version="1",features=["derive", "rc", "alloc", "std", "unstable", "serde_derive"]}
+"#;
+    let mut config = Config::new();
+    config.max_width = 60;
+    assert_eq!(
+        fmt(input, &config),
+        r#"[features]
+default = [
+    "std",
+    "derive",
+    "serde",
+    "rayon",
+    "tokio",
+    "macros",
+    "unstable",
+    "simd",
+]
+small = ["std", "derive"]
+
+[dependencies]
+serde = { version = "1", features = [
+    "derive",
+    "rc",
+    "alloc",
+    "std",
+    "unstable",
+    "serde_derive",
+] }
+"#
+    );
+}
+
+#[test]
+fn honors_layout_options() {
+    let input = r#"[package]
+authors = [ "a", "b" ]
+keywords = ["c","d",]
+metadata={ a=1 }
+list = ["a-long-element", "another-long-element", "and-another-one"]
+"#;
+    let mut config = Config::new();
+    config.max_width = 40;
+    config.multiline_trailing_comma = false;
+    config.compact_inline_tables = true;
+    config.indent_string = "\t".to_owned();
+    assert_eq!(
+        fmt(input, &config),
+        "[package]
+authors = [ \"a\", \"b\" ]
+keywords = [\"c\", \"d\"]
+metadata = {a = 1}
+list = [
+\t\"a-long-element\",
+\t\"another-long-element\",
+\t\"and-another-one\"
+]
+"
+    );
+
+    config.compact_arrays = true;
+    config.always_trailing_comma = true;
+    config.space_around_eq = false;
+    assert_eq!(fmt("authors = [ \"a\", \"b\" ]\n", &config), "authors=[\"a\", \"b\",]\n");
+}
+
+#[test]
+fn keeps_commented_and_skipped_values() {
+    let input = r#"[features]
+default = [
+    # The usual
+    "std",
+]
+# manifmt: skip
+full = [
+    "std",
+]
+"#;
+    assert_eq!(fmt(input, &Config::new()), input);
+}
+
+#[test]
+fn taplo_uses_the_shared_options() {
+    let input = r#"[package]
+name="foo"
+keywords = [ "a","b" ]
+
+[dependencies]
+serde = {version = "1",features=["derive"]}
+"#;
+    let mut config = Config::new();
+    config.compact_arrays = true;
+    assert_eq!(
+        super::taplo_fmt(input, &config),
+        r#"[package]
+name = "foo"
+keywords = ["a", "b"]
+
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+"#
+    );
+
+    config.space_around_eq = false;
+    config.crlf = true;
+    assert_eq!(super::taplo_fmt("a = 1\nb = 2\n", &config), "a=1\r\nb=2\r\n");
+}
+
+#[test]
+fn taplo_keeps_what_directives_cover() {
+    let input = r#"[package]
+name="foo"
+# manifmt: skip
+keywords = [ "a","b" ]
+edition="2021" # manifmt: skip
+
+# manifmt: skip
+[features]
+default = [ "std" ]
+
+[dependencies]
+# manifmt: off
+serde = {version = "1",features=["derive"]}
+log="0.4"
+# manifmt: on
+rand={version="0.8"}
+"#;
+    let mut config = Config::new();
+    config.compact_arrays = true;
+    assert_eq!(
+        super::taplo_fmt(input, &config),
+        r#"[package]
+name = "foo"
+# manifmt: skip
+keywords = [ "a","b" ]
+edition="2021" # manifmt: skip
+
+# manifmt: skip
+[features]
+default = [ "std" ]
+
+[dependencies]
+# manifmt: off
+serde = {version = "1",features=["derive"]}
+log="0.4"
+# manifmt: on
+rand = { version = "0.8" }
+"#
+    );
+}
+
+#[test]
+fn normalizes_quotes() {
+    let input = r#"[package]
+"name" = 'foo'
+description = "It\u0027s \"quoted\""
+path = 'C:\dir'
+tab = "a\u0009b"
+"needs quotes" = "x"
+doc = '''
+keep'''
+
+[dependencies."serde"]
+version = '1'
+"#;
+    let mut config = Config::new();
+    config.unquote_keys = true;
+    config.quote_style = QuoteStyle::Double;
+    assert_eq!(
+        fmt(input, &config),
+        r#"[package]
+name = "foo"
+description = "It's \"quoted\""
+path = 'C:\dir'
+tab = "a\tb"
+"needs quotes" = "x"
+doc = '''
+keep'''
+
+[dependencies.serde]
+version = "1"
+"#
+    );
+
+    config.unquote_keys = false;
+    config.quote_style = QuoteStyle::Single;
+    assert_eq!(
+        fmt(input, &config),
+        r#"[package]
+"name" = 'foo'
+description = "It's \"quoted\""
+path = 'C:\dir'
+tab = 'a	b'
+"needs quotes" = 'x'
+doc = '''
+keep'''
+
+[dependencies."serde"]
+version = '1'
+"#
+    );
+}
+
+#[test]
+fn tidies_blank_lines_and_whitespace() {
+    let input = "
+
+[package]
+
+name = \"foo\"   
+\tversion = \"1\"
+
+
+
+edition = \"2021\" # comment\t
+[dependencies]
+a = \"1\"
+# manifmt: off
+b = \"1\"   
+
+
+c = \"1\"
+# manifmt: on
+[features]
+
+
+";
+    assert_eq!(
+        fmt(input, &Config::new()),
+        "[package]
+name = \"foo\"
+    version = \"1\"
+
+edition = \"2021\" # comment
+
+[dependencies]
+a = \"1\"
+# manifmt: off
+b = \"1\"   
+
+
+c = \"1\"
+
+# manifmt: on
+[features]
+"
+    );
+
+    let mut config = Config::new();
+    config.key_value_newlines = false;
+    assert_eq!(fmt("a = 1\n\n# b\n\nb = 2\n\n\n", &config), "a = 1\n# b\nb = 2\n");
+}
+
+#[test]
+fn normalizes_and_aligns_comments() {
+    let input = r#"#!shebang-like
+##  section
+#comment
+[package]
+name = "foo" #the name
+version = "1"   # the version
+edition = "2021" # a trailing comment that is far too long to stay on the line after its value
+
+[dependencies]
+serde.workspace = true #  inherited
+anyhow = "1" # errors
+
+# Logging
+log = "0.4" # logs
+env_logger = "0.10" # to stderr
+"#;
+    let mut config = Config::new();
+    config.normalize_comments = true;
+    config.align_comments = true;
+    config.max_width = 60;
+    let expected = r#"#!shebang-like
+##  section
+# comment
+[package]
+name = "foo"  # the name
+version = "1" # the version
+# a trailing comment that is far too long to stay on the line after its value
+edition = "2021"
+
+[dependencies]
+serde.workspace = true # inherited
+anyhow = "1"           # errors
+
+# Logging
+log = "0.4"         # logs
+env_logger = "0.10" # to stderr
+"#;
+    assert_eq!(fmt(input, &config), expected);
+    assert_eq!(fmt(expected, &config), expected);
+
+    assert_eq!(fmt(input, &Config::new()), input);
+}
+
+#[test]
+fn aligns_entries() {
+    let input = r#"[package]
+name = "foo"
+version = "1"
+rust-version = "1.70"
+# comment
+edition = "2021"
+
+authors = ["a"] # author
+
+[dependencies]
+serde.workspace = true
+anyhow = "1"
+tokio = { version = "1", features = ["full"] }
+"#;
+    let mut config = Config::new();
+    config.align_entries = true;
+    let expected = r#"[package]
+name         = "foo"
+version      = "1"
+rust-version = "1.70"
+# comment
+edition = "2021"
+
+authors = ["a"] # author
+
+[dependencies]
+serde.workspace = true
+anyhow          = "1"
+tokio           = { version = "1", features = ["full"] }
+"#;
+    assert_eq!(fmt(input, &config), expected);
+    assert_eq!(fmt(expected, &config), expected);
+
+    config.space_around_eq = false;
+    assert_eq!(fmt("a = 1\nabc = 2\n", &config), "a  =1\nabc=2\n");
+    assert_eq!(super::taplo_fmt("a = 1\nabc = 2\n", &config), "a  =1\nabc=2\n");
+}
@@ -0,0 +1,207 @@
+//! Blank lines and whitespace between the lines of a document.
+//!
+//! Every table header gets exactly one blank line above it and none below it, other
+//! runs of blank lines are cut to `allowed_blank_lines`, or dropped between key value
+//! pairs without `key_value_newlines`. Trailing whitespace is removed and tabs used
+//! for indentation become `indent_string`.
+
+use toml_edit::{Decor, Document, Item, Table, Value};
+
+use crate::{
+    decor::{is_key_value, raw},
+    sort::{visit_headers, Directives},
+    Config,
+};
+
+/// Tidies the whitespace around the keys, values and headers of `toml`, the ones a
+/// directive covers are left as written.
+pub(super) fn tidy(toml: &mut Document, config: &Config, directives: &Directives) {
+    let newline = if toml.to_string().contains("\r\n") { "\r\n" } else { "\n" };
+    let tidy = Tidy { config, newline };
+
+    let root_has_values = toml.iter().any(|(_, item)| is_key_value(item));
+    if !directives.is_table_skipped("") {
+        tidy.keys("", toml.as_table_mut(), directives);
+    }
+    let first = {
+        let mut positions = vec![];
+        visit_headers(toml.as_table_mut(), &mut |_, table| {
+            positions.push(table.position())
+        });
+        positions.into_iter().flatten().min()
+    };
+    visit_headers(toml.as_table_mut(), &mut |path, table| {
+        if directives.is_table_skipped(path) {
+            return;
+        }
+        if !directives.is_table_pinned(path) {
+            let at_start = !root_has_values && table.position() == first;
+            tidy.header(table, at_start);
+        }
+        tidy.keys(path, table, directives);
+    });
+
+    // The blank lines at the end of the document go, the newline ending it is up to
+    // `trailing_newline`
+    let trailing = toml.trailing().as_str().unwrap_or_default();
+    let trailing = tidy.clean(trailing, true, true);
+    let trailing = trailing.trim_end();
+    if trailing.is_empty() {
+        toml.set_trailing("");
+    } else {
+        toml.set_trailing(format!("{}{}", trailing, newline));
+    }
+}
+
+struct Tidy<'a> {
+    config: &'a Config,
+    newline: &'static str,
+}
+
+impl Tidy<'_> {
+    /// One blank line above the header, or none at the start of the document.
+    fn header(&self, table: &mut Table, at_start: bool) {
+        let decor = table.decor_mut();
+        let prefix = self.clean(raw(decor.prefix()), true, false);
+        let prefix = strip_leading_blank_lines(&prefix);
+        if at_start {
+            decor.set_prefix(prefix);
+        } else {
+            decor.set_prefix(format!("{}{}", self.newline, prefix));
+        }
+        let suffix = self.clean(raw(decor.suffix()), false, true);
+        decor.set_suffix(suffix);
+    }
+
+    fn keys(&self, path: &str, table: &mut Table, directives: &Directives) {
+        let mut first = true;
+        for (mut key, item) in table.iter_mut() {
+            if !is_key_value(item) {
+                continue;
+            }
+            let is_first = std::mem::take(&mut first);
+            if directives.is_key_pinned(path, key.get()) {
+                continue;
+            }
+            let decor = key.decor_mut();
+            let prefix = self.clean(raw(decor.prefix()), true, false);
+            let prefix = if !self.config.key_value_newlines {
+                strip_blank_lines(&prefix, true)
+            } else if is_first {
+                strip_leading_blank_lines(&prefix)
+            } else {
+                prefix
+            };
+            decor.set_prefix(prefix);
+            self.item(item);
+        }
+    }
+
+    fn item(&self, item: &mut Item) {
+        match item {
+            Item::Value(value) => {
+                self.value(value);
+                let decor = value.decor_mut();
+                let suffix = self.clean(raw(decor.suffix()), false, true);
+                decor.set_suffix(suffix);
+            }
+            Item::Table(dotted) => {
+                for (_, item) in dotted.iter_mut() {
+                    self.item(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Tidies the lines inside of a multi-line array.
+    fn value(&self, value: &mut Value) {
+        match value {
+            Value::Array(arr) => {
+                for value in arr.iter_mut() {
+                    self.decor(value.decor_mut());
+                    self.value(value);
+                }
+                let trailing = self.clean(raw(Some(arr.trailing())), false, false);
+                arr.set_trailing(trailing);
+            }
+            Value::InlineTable(table) => {
+                for (_, value) in table.iter_mut() {
+                    self.value(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn decor(&self, decor: &mut Decor) {
+        let prefix = self.clean(raw(decor.prefix()), false, false);
+        let suffix = self.clean(raw(decor.suffix()), false, false);
+        decor.set_prefix(prefix);
+        decor.set_suffix(suffix);
+    }
+
+    /// Removes the whitespace at the end of lines, replaces the tabs indenting them and
+    /// cuts runs of blank lines to `allowed_blank_lines`. `starts_line` and
+    /// `ends_line` say whether `raw` starts at the start of a line and ends at its end.
+    fn clean(&self, raw: &str, starts_line: bool, ends_line: bool) -> String {
+        let lines = raw.split('\n').collect::<Vec<_>>();
+        let last = lines.len() - 1;
+        let mut out = String::new();
+        let mut blanks = 0;
+        for (idx, line) in lines.into_iter().enumerate() {
+            let (line, cr) = match line.strip_suffix('\r') {
+                Some(line) => (line, "\r"),
+                None => (line, ""),
+            };
+            let full_line = idx != 0 || starts_line;
+            let line = if idx != last || ends_line {
+                line.trim_end_matches([' ', '\t'])
+            } else {
+                line
+            };
+            let tab = if full_line { self.config.indent_string.as_str() } else { " " };
+            let line = untab(line, tab);
+            if idx == last {
+                out.push_str(&line);
+                break;
+            }
+            if full_line && line.is_empty() {
+                blanks += 1;
+                if blanks > self.config.allowed_blank_lines {
+                    continue;
+                }
+            } else {
+                blanks = 0;
+            }
+            out.push_str(&line);
+            out.push_str(cr);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Replaces the tabs in the whitespace a line starts with by `tab`.
+fn untab(line: &str, tab: &str) -> String {
+    let content = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - content.len()];
+    format!("{}{}", indent.replace('\t', tab), content)
+}
+
+fn strip_leading_blank_lines(prefix: &str) -> String { strip_blank_lines(prefix, false) }
+
+/// Drops the blank lines of `prefix`, only the ones it starts with unless `all`.
+fn strip_blank_lines(prefix: &str, all: bool) -> String {
+    let mut out = String::new();
+    let mut leading = true;
+    for line in prefix.split_inclusive('\n') {
+        let blank = line.ends_with('\n') && line.trim().is_empty();
+        if blank && (leading || all) {
+            continue;
+        }
+        leading = false;
+        out.push_str(line);
+    }
+    out
+}
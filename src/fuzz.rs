@@ -25,7 +25,9 @@ fn main() {
                         "build-dependencies".to_owned(),
                         "dev-dependencies".to_owned(),
                     ],
-                );
+                    &Config::new(),
+                )
+                .unwrap();
                 fmt::fmt_toml(&mut toml, &Config::new());
                 let s = toml.to_string_in_original_order();
                 assert!(s.parse::<Document>().is_ok())
@@ -0,0 +1,62 @@
+//! Finding the manifests touched by a change with the local `git` command.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::IoResult;
+
+mod test;
+
+/// Returns the absolute paths of the `Cargo.toml` files of the repository `dir` is in
+/// that changed since `rev`, or that have staged changes if `staged` is set.
+///
+/// Deleted manifests are left out.
+pub(crate) fn changed_manifests(
+    dir: &Path,
+    rev: Option<&str>,
+    staged: bool,
+) -> IoResult<Vec<PathBuf>> {
+    let toplevel = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end());
+
+    let mut args = vec!["diff", "--name-only", "--no-renames", "--diff-filter=d", "-z"];
+    if staged {
+        args.push("--cached");
+    }
+    args.extend(rev);
+    args.push("--");
+
+    Ok(git(dir, &args)?
+        .split('\0')
+        .filter(|p| Path::new(p).file_name().is_some_and(|f| f == "Cargo.toml"))
+        .map(|p| toplevel.join(p))
+        .collect())
+}
+
+/// Returns the contents of the staged version of `path`.
+pub(crate) fn staged_contents(path: &Path) -> IoResult<String> {
+    let dir =
+        path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let toplevel = PathBuf::from(git(dir, &["rev-parse", "--show-toplevel"])?.trim_end());
+    let relative = path.strip_prefix(&toplevel).unwrap_or(path);
+    git(dir, &["show", &format!(":{}", relative.to_string_lossy().replace('\\', "/"))])
+}
+
+/// Runs `git` with `args` in `dir`.
+fn git(dir: &Path, args: &[&str]) -> IoResult<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )
+        .into());
+    }
+    String::from_utf8(output.stdout).map_err(Into::into)
+}
@@ -0,0 +1,55 @@
+#![cfg(test)]
+use std::{fs, path::Path};
+
+use pretty_assertions::assert_eq;
+
+use super::{changed_manifests, git, staged_contents};
+
+fn commit_all(dir: &Path) {
+    git(dir, &["add", "-A"]).unwrap();
+    git(
+        dir,
+        &[
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "-qm",
+            "test",
+        ],
+    )
+    .unwrap();
+}
+
+#[test]
+fn finds_changed_and_staged_manifests() {
+    let dir = fs::canonicalize(crate::test::temp_dir("git")).unwrap();
+    git(&dir, &["init", "-q"]).unwrap();
+    for member in ["a", "b", "c"] {
+        fs::create_dir(dir.join(member)).unwrap();
+        fs::write(dir.join(member).join("Cargo.toml"), "[package]\n").unwrap();
+    }
+    fs::write(dir.join("notes.toml"), "").unwrap();
+    commit_all(&dir);
+
+    // `a` is changed in the working tree, `b` is staged and `c` is deleted
+    fs::write(dir.join("a/Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+    fs::write(dir.join("b/Cargo.toml"), "[package]\nname = \"b\"\n").unwrap();
+    git(&dir, &["add", "b/Cargo.toml"]).unwrap();
+    fs::remove_file(dir.join("c/Cargo.toml")).unwrap();
+    fs::write(dir.join("notes.toml"), "a = 1\n").unwrap();
+
+    let changed = changed_manifests(&dir.join("a"), Some("HEAD"), false).unwrap();
+    assert_eq!(changed, [dir.join("a/Cargo.toml"), dir.join("b/Cargo.toml")]);
+    let staged = changed_manifests(&dir, None, true).unwrap();
+    assert_eq!(staged, [dir.join("b/Cargo.toml")]);
+
+    // The staged version is read, not the one in the working tree
+    fs::write(dir.join("b/Cargo.toml"), "[package]\nname = \"unstaged\"\n").unwrap();
+    let staged = staged_contents(&dir.join("b/Cargo.toml")).unwrap();
+    assert_eq!(staged, "[package]\nname = \"b\"\n");
+    fs::remove_dir_all(dir).unwrap();
+}
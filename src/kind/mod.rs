@@ -0,0 +1,165 @@
+//! The kinds of toml files we know how to sort, each with its own defaults.
+
+use std::path::{Path, PathBuf};
+
+use toml::Table;
+
+use crate::{
+    config::{config_dirs, is_workspace_root},
+    sort::Matcher,
+    Config,
+};
+
+mod test;
+
+/// A kind of toml file, picked from the file name unless `--kind` is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Kind {
+    /// A `Cargo.toml` manifest.
+    Cargo,
+    /// A `.cargo/config.toml` file.
+    CargoConfig,
+    /// A `rust-toolchain.toml` file.
+    RustToolchain,
+    /// A cargo-deny `deny.toml` file.
+    Deny,
+    /// A python `pyproject.toml` file.
+    Pyproject,
+    /// Any other toml file, only nested tables of the same heading are sorted.
+    Other,
+}
+
+const CARGO_CONFIG_MATCHER: Matcher<'_> =
+    Matcher { heading: &["alias", "env"], heading_key: &[], cargo_dependencies: false };
+
+const CARGO_CONFIG_ORDER: &[&str] = &[
+    "alias",
+    "build",
+    "env",
+    "target",
+    "profile",
+    "patch",
+    "source",
+    "registries",
+    "registry",
+    "net",
+    "http",
+    "term",
+];
+
+const RUST_TOOLCHAIN_MATCHER: Matcher<'_> = Matcher {
+    heading: &[],
+    heading_key: &[("toolchain", "components"), ("toolchain", "targets")],
+    cargo_dependencies: false,
+};
+
+const RUST_TOOLCHAIN_ORDER: &[&str] = &["toolchain"];
+
+const DENY_MATCHER: Matcher<'_> = Matcher {
+    heading: &[],
+    heading_key: &[
+        ("advisories", "ignore"),
+        ("licenses", "allow"),
+        ("licenses", "deny"),
+        ("bans", "deny"),
+        ("bans", "skip"),
+        ("bans", "skip-tree"),
+        ("sources", "allow-git"),
+        ("sources", "allow-registry"),
+    ],
+    cargo_dependencies: false,
+};
+
+const DENY_ORDER: &[&str] =
+    &["graph", "output", "advisories", "licenses", "bans", "sources"];
+
+const PYPROJECT_MATCHER: Matcher<'_> = Matcher {
+    heading: &[],
+    heading_key: &[
+        ("build-system", "requires"),
+        ("project", "classifiers"),
+        ("project", "dependencies"),
+        ("project", "keywords"),
+        ("project", "optional-dependencies"),
+    ],
+    cargo_dependencies: false,
+};
+
+const PYPROJECT_ORDER: &[&str] = &["build-system", "project", "tool"];
+
+const OTHER_MATCHER: Matcher<'_> =
+    Matcher { heading: &[], heading_key: &[], cargo_dependencies: false };
+
+impl Kind {
+    /// Guesses the kind of a toml file from its name.
+    pub(crate) fn from_path(path: &Path) -> Self {
+        let in_cargo_dir =
+            path.parent().and_then(Path::file_name).is_some_and(|dir| dir == ".cargo");
+        match path.file_name().and_then(|f| f.to_str()) {
+            Some("Cargo.toml") => Self::Cargo,
+            Some("config.toml" | "config") if in_cargo_dir => Self::CargoConfig,
+            Some("rust-toolchain.toml" | "rust-toolchain") => Self::RustToolchain,
+            Some("deny.toml") => Self::Deny,
+            Some("pyproject.toml") => Self::Pyproject,
+            _ => Self::Other,
+        }
+    }
+
+    /// The headings and keys whose contents are sorted.
+    pub(crate) fn matcher(self) -> Matcher<'static> {
+        match self {
+            Self::Cargo => crate::sort::MATCHER,
+            Self::CargoConfig => CARGO_CONFIG_MATCHER,
+            Self::RustToolchain => RUST_TOOLCHAIN_MATCHER,
+            Self::Deny => DENY_MATCHER,
+            Self::Pyproject => PYPROJECT_MATCHER,
+            Self::Other => OTHER_MATCHER,
+        }
+    }
+
+    /// The order top level tables are written in.
+    ///
+    /// The `table_order` of the config only applies to `Cargo.toml` manifests, every
+    /// other kind has a fixed order.
+    pub(crate) fn table_order(self, config: &Config) -> Vec<String> {
+        let order = match self {
+            Self::Cargo => return config.table_order.clone(),
+            Self::CargoConfig => CARGO_CONFIG_ORDER,
+            Self::RustToolchain => RUST_TOOLCHAIN_ORDER,
+            Self::Deny => DENY_ORDER,
+            Self::Pyproject => PYPROJECT_ORDER,
+            Self::Other => &[],
+        };
+        order.iter().map(ToString::to_string).collect()
+    }
+
+    /// Whether `clean_tables` applies, it only knows which empty tables of a
+    /// `Cargo.toml` mean nothing.
+    pub(crate) fn clean_tables(self, config: &Config) -> bool {
+        self == Self::Cargo && config.clean_tables
+    }
+}
+
+/// Whether the file at `path` is toml, an extensionless `rust-toolchain` may also be
+/// in the older format that only names the channel and is skipped then.
+pub(crate) fn is_toml(path: &Path) -> bool {
+    path.file_name().is_none_or(|f| f != "rust-toolchain")
+        || std::fs::read_to_string(path).map_or(true, |raw| raw.parse::<Table>().is_ok())
+}
+
+/// Returns the `.cargo/config.toml` files that apply to the crate at `manifest`, from
+/// the crate's own directory up to the workspace root.
+///
+/// Outside of a workspace only the crate's own directory is searched, we never want
+/// to rewrite the config in the user's home directory.
+pub(crate) fn find_cargo_configs(manifest: &Path) -> Vec<PathBuf> {
+    let dirs = config_dirs(manifest);
+    let in_workspace = dirs.last().is_some_and(|dir| is_workspace_root(dir));
+    let searched = if in_workspace { dirs.len() } else { 1 };
+
+    dirs.iter()
+        .take(searched)
+        .flat_map(|dir| [dir.join(".cargo/config.toml"), dir.join(".cargo/config")])
+        .filter(|path| path.is_file())
+        .collect()
+}
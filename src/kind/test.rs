@@ -0,0 +1,129 @@
+#![cfg(test)]
+use std::{fs, path::Path};
+
+use pretty_assertions::assert_eq;
+
+use super::Kind;
+use crate::{sort, Config};
+
+fn sort_as(path: &str, kind: Kind) -> String {
+    assert_eq!(Kind::from_path(Path::new(path)), kind);
+    let input = fs::read_to_string(path).unwrap();
+    let order = kind.table_order(&Config::new());
+    sort::sort_toml(&input, kind.matcher(), false, &order, &Config::new())
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn kind_from_file_name() {
+    assert_eq!(Kind::from_path(Path::new("crate/Cargo.toml")), Kind::Cargo);
+    assert_eq!(Kind::from_path(Path::new(".cargo/config")), Kind::CargoConfig);
+    assert_eq!(Kind::from_path(Path::new("rust-toolchain")), Kind::RustToolchain);
+    assert_eq!(Kind::from_path(Path::new("config.toml")), Kind::Other);
+    assert_eq!(Kind::from_path(Path::new("taplo.toml")), Kind::Other);
+}
+
+#[test]
+fn sort_rust_toolchain() {
+    let sorted = sort_as("fixtures/kinds/rust-toolchain.toml", Kind::RustToolchain);
+    assert_eq!(
+        sorted,
+        r#"[toolchain]
+channel = "1.74"
+components = ["clippy", "rust-src", "rustfmt"]
+targets = ["aarch64-apple-darwin", "wasm32-unknown-unknown"]
+"#
+    );
+}
+
+#[test]
+fn sort_deny() {
+    let sorted = sort_as("fixtures/kinds/deny.toml", Kind::Deny);
+    assert_eq!(
+        sorted,
+        r#"[advisories]
+ignore = ["RUSTSEC-2019-0001", "RUSTSEC-2020-0071"]
+
+[licenses]
+allow = ["Apache-2.0", "BSD-3-Clause", "ISC", "MIT"]
+
+[bans]
+multiple-versions = "warn"
+deny = [{ name = "openssl" }, { name = "git2" }]
+"#
+    );
+}
+
+#[test]
+fn sort_pyproject() {
+    let sorted = sort_as("fixtures/kinds/pyproject.toml", Kind::Pyproject);
+    let build_system = sorted.find("[build-system]").unwrap();
+    assert!(build_system < sorted.find("[project]").unwrap());
+    assert!(sorted.contains("dependencies = [\"attrs\", \"click\", \"requests>=2\"]"));
+    assert!(
+        sorted.contains("[project.optional-dependencies]\ndocs = [\"sphinx\"]\ntest = ")
+    );
+}
+
+#[test]
+fn pyproject_extras_ignore_dependency_groups() {
+    let input = fs::read_to_string("fixtures/kinds/pyproject.toml").unwrap();
+    let config: Config = toml::from_str(
+        "dependency_groups = { testing = [\"test\"] }\ndependency_group_headers = true",
+    )
+    .unwrap();
+    let order = Kind::Pyproject.table_order(&config);
+    let sorted =
+        sort::sort_toml(&input, Kind::Pyproject.matcher(), false, &order, &config)
+            .unwrap()
+            .to_string();
+    assert!(
+        sorted.contains("[project.optional-dependencies]\ndocs = [\"sphinx\"]\ntest = ")
+    );
+    assert!(!sorted.contains("# testing"), "{}", sorted);
+}
+
+#[test]
+fn legacy_rust_toolchain_is_skipped() {
+    let dir = crate::test::temp_dir("toolchain");
+    let path = dir.join("rust-toolchain");
+    fs::write(&path, "stable\n").unwrap();
+    assert!(!super::is_toml(&path));
+    fs::write(&path, "[toolchain]\nchannel = \"stable\"\n").unwrap();
+    assert!(super::is_toml(&path));
+    assert!(super::is_toml(Path::new("fixtures/kinds/deny.toml")));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn sort_cargo_config() {
+    let sorted = sort_as("fixtures/kinds/.cargo/config.toml", Kind::CargoConfig);
+    assert_eq!(
+        sorted,
+        r#"[alias]
+b = "build"
+xtask = "run --package xtask --"
+
+[env]
+CC = "clang"
+RUST_LOG = "info"
+"#
+    );
+}
+
+#[test]
+fn only_cargo_manifests_are_cleaned() {
+    let config = Config { clean_tables: true, ..Config::new() };
+    let input = "[tool.black]\n\n[features]\n";
+    let sorted = crate::sort_manifest(
+        Path::new("pyproject.toml"),
+        input,
+        &config,
+        Kind::Pyproject,
+    );
+    assert_eq!(sorted.unwrap(), input);
+    let sorted =
+        crate::sort_manifest(Path::new("Cargo.toml"), input, &config, Kind::Cargo);
+    assert_eq!(sorted.unwrap(), "[tool.black]\n");
+}
@@ -1,18 +1,31 @@
 use std::{
+    collections::BTreeSet,
     env,
     fmt::Display,
     fs::{self, read_to_string, OpenOptions},
     io::{Read, Write},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
+    sync::atomic::{self, AtomicUsize},
+    thread,
 };
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 pub(crate) use config::Config;
+use config::{Formatter, Preset};
+use itertools::Itertools;
+use kind::Kind;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use toml_edit::{Document, Item};
 
 mod config;
+mod decor;
+mod fmt;
+mod git;
+mod kind;
 mod sort;
+mod test;
+mod verify;
 
 type IoResult<T> = Result<T, Box<dyn std::error::Error>>;
 
@@ -23,6 +36,11 @@ struct Args {
     #[arg(short, long)]
     check: bool,
 
+    /// Formats the sorted toml with the builtin formatter when the config does not
+    /// pick a formatter
+    #[arg(long)]
+    format: bool,
+
     /// Prints Cargo.toml, lexically sorted, to stdout
     #[arg(short, long)]
     // todo .conflicts_with("check")
@@ -36,6 +54,88 @@ struct Args {
     /// package,dependencies,features)
     #[arg(short, long)]
     order: Option<Vec<String>>,
+
+    /// Keeps a copy of the original file next to it as Cargo.toml.orig
+    #[arg(long)]
+    backup: bool,
+
+    /// Lists the files that would be rewritten without writing anything
+    #[arg(long, conflicts_with_all = ["check", "print"])]
+    dry_run: bool,
+
+    /// Reads a manifest from stdin and writes the sorted result to stdout
+    #[arg(long, conflicts_with_all = ["check", "print", "workspace", "dry_run", "backup"])]
+    stdin: bool,
+
+    /// The path of the manifest read with --stdin, used to find the config and in
+    /// error messages
+    #[arg(long, requires = "stdin")]
+    stdin_filepath: Option<PathBuf>,
+
+    /// Uses this config file instead of searching for tomlfmt.toml files
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Starts the config out with this preset instead of the one the config files
+    /// select, the keys they set still override it
+    #[arg(long, value_enum, global = true)]
+    preset: Option<Preset>,
+
+    /// The number of manifests processed at the same time, defaults to the number of
+    /// CPUs
+    #[arg(short, long)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Only checks the Cargo.toml files that changed in git since this revision
+    #[arg(long, value_name = "REV", conflicts_with = "stdin")]
+    changed_since: Option<String>,
+
+    /// Only checks the staged version of the Cargo.toml files with staged changes
+    #[arg(long, requires = "check", conflicts_with = "stdin")]
+    staged: bool,
+
+    /// Treats every file as this kind of toml file instead of guessing it from the
+    /// file name
+    #[arg(long, value_enum)]
+    kind: Option<Kind>,
+
+    /// Also checks the .cargo/config.toml files from each crate up to the workspace
+    /// root
+    #[arg(long)]
+    cargo_config: bool,
+
+    /// The toml files or crate directories to check, defaults to the current
+    /// directory
+    #[arg(conflicts_with_all = ["stdin", "changed_since", "staged"])]
+    paths: Vec<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspects the config that applies to a manifest
+    Config {
+        /// Prints the effective config, after merging every config file that applies
+        #[arg(long, required = true)]
+        show: bool,
+
+        /// The manifest or crate directory to show the config for, defaults to the
+        /// current directory
+        path: Option<PathBuf>,
+    },
+    /// Writes a tomlfmt.toml with the default config and every key documented
+    Init {
+        /// Infers the line endings, table order and trailing commas from the
+        /// manifests of the workspace
+        #[arg(long)]
+        infer: bool,
+
+        /// Overwrites an existing tomlfmt.toml
+        #[arg(long)]
+        force: bool,
+    },
 }
 
 fn main() {
@@ -54,35 +154,81 @@ fn run() -> IoResult<()> {
         env::current_dir().map_err(|e| format!("no current directory found: {}", e))?;
     let cwd_str = cwd.to_string_lossy();
 
-    let config_file = fs::File::open(cwd.join("tomlfmt.toml"))
-        .or_else(|_| fs::File::open(".tomlfmt.toml"));
+    if let Some(Command::Config { path, .. }) = &args.command {
+        let path = path.as_ref().map_or(cwd.clone(), |p| cwd.join(p));
+        let config = load_config(&manifest_path(&path.to_string_lossy()), &args)?;
+        print!("{}", toml::to_string(&config)?);
+        return Ok(());
+    }
+
+    if let Some(Command::Init { infer, force }) = &args.command {
+        return init_config(&cwd, *infer, *force);
+    }
 
-    let mut config = if let Ok(mut f) = config_file {
-        let mut s = String::new();
-        f.read_to_string(&mut s)?;
-        toml::from_str(&s)?
+    if args.stdin {
+        return sort_stdin(
+            &args,
+            &cwd,
+            std::io::stdin().lock(),
+            std::io::stdout().lock(),
+        );
+    }
+    let member_paths = if args.changed_since.is_some() || args.staged {
+        git::changed_manifests(&cwd, args.changed_since.as_deref(), args.staged)?
+    } else if args.paths.is_empty() {
+        find_members(cwd_str.to_string())?.iter().map(|p| manifest_path(p)).collect()
+    } else {
+        let mut paths = vec![];
+        for path in
+            args.paths.iter().map(|p| manifest_path(&cwd.join(p).to_string_lossy()))
+        {
+            if Kind::from_path(&path) == Kind::Cargo {
+                let dir = path.parent().ok_or("no crate folder found")?;
+                paths.extend(
+                    find_members(dir.to_string_lossy().to_string())?
+                        .iter()
+                        .map(|p| manifest_path(p)),
+                );
+            } else if kind::is_toml(&path) {
+                paths.push(path);
+            } else {
+                let note = format!("skipping {}: not a toml file", path.display());
+                write_yellow("note: ", note)?;
+            }
+        }
+        paths.into_iter().unique().collect()
+    };
+    let member_paths = if args.cargo_config {
+        let configs = member_paths.iter().flat_map(|p| kind::find_cargo_configs(p));
+        member_paths.iter().cloned().chain(configs.collect::<Vec<_>>()).unique().collect()
     } else {
-        Config::serde_default()
+        member_paths
     };
-
-    if let Some(ordering) = &args.order {
-        config.table_order = ordering.iter().map(|s| s.to_string()).collect();
+    let jobs =
+        args.jobs.or_else(|| thread::available_parallelism().ok()).map_or(1, |j| j.get());
+    let checked = par_map(&member_paths, jobs, |path| {
+        load_config(path, &args)
+            .and_then(|config| {
+                let kind = args.kind.unwrap_or_else(|| Kind::from_path(path));
+                let warnings = order_sensitive_warnings(&config, kind);
+                check_toml(path, &args, &config)
+                    .map(|(status, sorted)| (status, sorted, warnings))
+            })
+            .map_err(|e| e.to_string())
+    });
+
+    let mut summary = Summary::default();
+    for (path, checked) in member_paths.iter().zip(checked) {
+        summary.report(path, checked, &args)?;
     }
-    let member_paths = find_members(cwd_str.to_string())?;
-    let members_sorted: IoResult<Vec<bool>> =
-        member_paths.iter().map(|path| check_toml(path, &args, &config)).collect();
-    let all_sorted = members_sorted?.iter().all(|x| *x);
 
-    if all_sorted { std::process::exit(0) } else { std::process::exit(1) }
+    if summary.finish(&args)? { std::process::exit(0) } else { std::process::exit(1) }
 }
 
 fn find_members(dir: String) -> IoResult<Vec<String>> {
     let mut filtered_matches = vec![dir.clone()];
 
-    let mut path = PathBuf::from(&dir);
-    if path.extension().is_none() {
-        path.push("Cargo.toml");
-    }
+    let path = manifest_path(&dir);
 
     let raw_toml = read_to_string(&path)
         .map_err(|_| format!("no file found at: {}", path.display()))?;
@@ -142,50 +288,291 @@ fn find_members(dir: String) -> IoResult<Vec<String>> {
     Ok(filtered_matches)
 }
 
-fn check_toml(path: &str, args: &Args, config: &Config) -> IoResult<bool> {
-    let mut path = PathBuf::from(path);
-    if path.extension().is_none() {
-        path.push("Cargo.toml");
+/// The outcome of `check_toml` along with the warnings about the file's config, errors
+/// are turned into their message so they can be sent between threads.
+type Checked = Result<(Status, String, Vec<String>), String>;
+
+/// What `check_toml` did, or in `--check` and `--dry-run` mode would do, to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// The file is already sorted and was left untouched.
+    Unchanged,
+    /// The file is not sorted.
+    Changed,
+}
+
+/// Tallies the per-file results of a run and prints a status line for each.
+#[derive(Default)]
+struct Summary {
+    changed: usize,
+    unchanged: usize,
+    failed: usize,
+    /// Warnings already printed, files sharing a config would repeat them.
+    warned: BTreeSet<String>,
+}
+
+impl Summary {
+    /// Prints the status line of a single file, or its sorted contents in `--print`
+    /// mode.
+    fn report(&mut self, path: &Path, checked: Checked, args: &Args) -> IoResult<()> {
+        let path = path.display();
+        if let Ok((_, _, warnings)) = &checked {
+            for warning in warnings {
+                if self.warned.insert(warning.clone()) {
+                    write_yellow("warning: ", warning)?;
+                }
+            }
+        }
+        if let (true, Ok((_, sorted, _))) = (args.print, &checked) {
+            print!("{}", sorted);
+        }
+        match checked.map(|(status, ..)| status) {
+            Ok(Status::Unchanged) => {
+                self.unchanged += 1;
+                if !args.print {
+                    write_green(
+                        if args.check { "Sorted: " } else { "Unchanged: " },
+                        path,
+                    )?;
+                }
+            }
+            Ok(Status::Changed) => {
+                self.changed += 1;
+                if args.check {
+                    write_red("Unsorted: ", path)?;
+                } else if args.dry_run {
+                    write_green("Would rewrite: ", path)?;
+                } else if !args.print {
+                    write_green("Rewritten: ", path)?;
+                }
+            }
+            Err(e) => {
+                self.failed += 1;
+                write_red("Failed: ", format!("{}: {}", path, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Prints the totals and returns whether the run succeeded.
+    fn finish(&self, args: &Args) -> IoResult<bool> {
+        if !args.print {
+            let changed = if args.check {
+                "unsorted"
+            } else if args.dry_run {
+                "to rewrite"
+            } else {
+                "rewritten"
+            };
+            write_green(
+                "Finished: ",
+                format!(
+                    "{} {}, {} unchanged, {} failed",
+                    self.changed, changed, self.unchanged, self.failed
+                ),
+            )?;
+        }
+        Ok(self.failed == 0 && !(args.check && self.changed > 0))
     }
+}
 
-    let dirname =
-        path.components().nth_back(1).ok_or("No crate folder found")?.as_os_str();
-    write_green("Checking ", format!("{}...", dirname.to_string_lossy()))?;
+/// Writes a documented `tomlfmt.toml` into `dir`.
+fn init_config(dir: &Path, infer: bool, force: bool) -> IoResult<()> {
+    let path = dir.join("tomlfmt.toml");
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        )
+        .into());
+    }
 
-    let toml_raw = read_to_string(&path)
-        .map_err(|_| format!("No file found at: {}", path.display()))?;
+    let mut config = Config::serde_default();
+    if infer {
+        let manifests = find_members(dir.to_string_lossy().to_string())?
+            .iter()
+            .flat_map(|member| read_to_string(manifest_path(member)))
+            .collect::<Vec<_>>();
+        config.infer_from(&manifests);
+    }
 
-    let sorted = sort::sort_toml(&toml_raw, sort::MATCHER, false, &config.table_order);
+    write_file(&path, &config.to_commented_toml()?, false)?;
+    write_green("Created: ", path.display())
+}
+
+/// Finds the config for the manifest at `path` and applies the command line
+/// overrides to it.
+fn load_config(path: &Path, args: &Args) -> IoResult<Config> {
+    let mut config = Config::resolve(path, args.config.as_deref(), args.preset)?;
+    if let Some(ordering) = &args.order {
+        config.table_order = ordering.iter().map(|s| s.to_string()).collect();
+    }
+    if args.format && config.formatter == Formatter::Off {
+        config.formatter = Formatter::Builtin;
+    }
+    Ok(config)
+}
+
+/// Turns a crate directory into the path of its manifest, any other path is kept as
+/// is.
+fn manifest_path(path: &str) -> PathBuf {
+    let mut path = PathBuf::from(path);
+    if path.is_dir() {
+        path.push("Cargo.toml");
+    }
+    path
+}
+
+/// Sorts and, unless `formatter` is off, formats a toml file the way it is written out,
+/// `name` is only used in errors.
+fn sort_manifest(
+    name: &Path,
+    toml_raw: &str,
+    config: &Config,
+    kind: Kind,
+) -> IoResult<String> {
+    let config = &Config { clean_tables: kind.clean_tables(config), ..config.clone() };
+    let mut sorted = sort::sort_toml(
+        toml_raw,
+        kind.matcher(),
+        false,
+        &kind.table_order(config),
+        config,
+    )
+    .map_err(|e| format!("failed to parse {}: {}", name.display(), e))?;
+    if kind == Kind::CargoConfig && config.sort_rustflags {
+        sort::sort_rustflags(&mut sorted, config);
+    }
+    match config.formatter {
+        Formatter::Builtin => fmt::fmt_toml(&mut sorted, config),
+        Formatter::Taplo => fmt::normalize_quotes(&mut sorted, config),
+        Formatter::Off => {}
+    }
     let mut sorted_str = sorted.to_string();
-    let is_sorted = toml_raw == sorted_str;
+    if config.formatter == Formatter::Taplo {
+        sorted_str = fmt::taplo_fmt(&sorted_str, config);
+    }
+    if config.formatter != Formatter::Off && !config.trailing_newline {
+        sorted_str.truncate(sorted_str.trim_end_matches(['\r', '\n']).len());
+    }
 
     if config.crlf && !sorted_str.contains("\r\n") {
         sorted_str = sorted_str.replace('\n', "\r\n")
     }
+    Ok(sorted_str)
+}
+
+/// Warns about the arrays `kind` would sort that `order_sensitive_arrays` protects,
+/// those are left as they are.
+fn order_sensitive_warnings(config: &Config, kind: Kind) -> Vec<String> {
+    let mut rules = kind
+        .matcher()
+        .heading_key
+        .iter()
+        .map(|(heading, key)| format!("{}.{}", heading, key))
+        .collect::<Vec<_>>();
+    if kind == Kind::CargoConfig && config.sort_rustflags {
+        rules.extend(sort::RUSTFLAGS.iter().map(ToString::to_string));
+    }
+    rules
+        .iter()
+        .filter_map(|rule| {
+            config.order_sensitive(rule).map(|pat| {
+                format!(
+                    "`{}` matches `{}` in `order_sensitive_arrays`, it is left unsorted",
+                    rule, pat
+                )
+            })
+        })
+        .collect()
+}
+
+/// Sorts the manifest read from `input`, which is stdin, and writes the result to
+/// `output`, which is stdout.
+fn sort_stdin(
+    args: &Args,
+    cwd: &Path,
+    mut input: impl Read,
+    mut output: impl Write,
+) -> IoResult<()> {
+    let name = args.stdin_filepath.as_deref().unwrap_or_else(|| Path::new("<stdin>"));
+    let config = match &args.stdin_filepath {
+        Some(path) => load_config(&cwd.join(path), args)?,
+        None => load_config(&cwd.join("Cargo.toml"), args)?,
+    };
 
-    if args.print {
-        print!("{}", sorted_str);
-        return Ok(true);
+    let mut toml_raw = String::new();
+    input
+        .read_to_string(&mut toml_raw)
+        .map_err(|e| format!("failed to read {}: {}", name.display(), e))?;
+
+    let kind = args.kind.unwrap_or_else(|| match &args.stdin_filepath {
+        Some(path) => Kind::from_path(path),
+        None => Kind::Cargo,
+    });
+    for warning in order_sensitive_warnings(&config, kind) {
+        write_yellow("warning: ", warning)?;
     }
+    let sorted_str = sort_manifest(name, &toml_raw, &config, kind)?;
+    verify::ensure_equivalent(&toml_raw, &sorted_str, kind, &config)?;
+
+    output.write_all(sorted_str.as_bytes())?;
+    output.flush().map_err(Into::into)
+}
+
+/// Sorts the manifest at `path` and, unless `--check`, `--print` or `--dry-run` is
+/// given, writes it back if that changed it. Returns the sorted manifest.
+fn check_toml(path: &Path, args: &Args, config: &Config) -> IoResult<(Status, String)> {
+    let toml_raw = if args.staged {
+        git::staged_contents(path)?
+    } else {
+        read_to_string(path)
+            .map_err(|_| format!("No file found at: {}", path.display()))?
+    };
+
+    let kind = args.kind.unwrap_or_else(|| Kind::from_path(path));
+    let sorted_str = sort_manifest(path, &toml_raw, config, kind)?;
+    let status = if toml_raw == sorted_str { Status::Unchanged } else { Status::Changed };
 
     if args.check {
-        if !is_sorted {
-            write_red(
-                "error: ",
-                format!("Dependencies for {} are not sorted", dirname.to_string_lossy()),
-            )?;
-        }
+        return Ok((status, sorted_str));
+    }
 
-        return Ok(is_sorted);
+    verify::ensure_equivalent(&toml_raw, &sorted_str, kind, config)?;
+    if status == Status::Changed && !args.print && !args.dry_run {
+        write_file(path, &sorted_str, args.backup)?;
     }
 
-    write_file(&path, &sorted_str)?;
-    write_green(
-        "Finished: ",
-        format!("Cargo.toml for {:?} has been rewritten", dirname.to_string_lossy()),
-    )?;
+    Ok((status, sorted_str))
+}
 
-    Ok(true)
+/// Calls `f` on every item using up to `jobs` threads, the results are returned in
+/// the order of `items`.
+fn par_map<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results = thread::scope(|scope| {
+        let workers = (0..jobs.min(items.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = vec![];
+                    loop {
+                        let idx = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        let Some(item) = items.get(idx) else {
+                            return done;
+                        };
+                        done.push((idx, f(item)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        workers.into_iter().flat_map(|w| w.join().unwrap()).collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, r)| r).collect()
 }
 
 fn write_red<S: Display>(highlight: &str, msg: S) -> IoResult<()> {
@@ -196,6 +583,14 @@ fn write_red<S: Display>(highlight: &str, msg: S) -> IoResult<()> {
     writeln!(stderr, "{}", msg).map_err(Into::into)
 }
 
+fn write_yellow<S: Display>(highlight: &str, msg: S) -> IoResult<()> {
+    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+    stderr.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
+    write!(stderr, "{}", highlight)?;
+    stderr.reset()?;
+    writeln!(stderr, "{}", msg).map_err(Into::into)
+}
+
 fn write_green<S: Display>(highlight: &str, msg: S) -> IoResult<()> {
     let mut stdout = StandardStream::stdout(ColorChoice::Auto);
     stdout.set_color(ColorSpec::new().set_fg(Some(Color::Green)))?;
@@ -204,7 +599,38 @@ fn write_green<S: Display>(highlight: &str, msg: S) -> IoResult<()> {
     writeln!(stdout, "{}", msg).map_err(Into::into)
 }
 
-fn write_file<P: AsRef<Path>>(path: P, toml: &str) -> IoResult<()> {
-    let mut fd = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
-    write!(fd, "{}", toml).map_err(Into::into)
+/// Writes `toml` to a temporary file next to `path` and renames it into place, so a
+/// crash never leaves a half written manifest behind.
+///
+/// A symlink is followed so the file it points to is replaced, not the link. The
+/// permissions of the file being replaced are kept, if `backup` is set the original
+/// is first copied to `<file name>.orig`.
+fn write_file<P: AsRef<Path>>(path: P, toml: &str, backup: bool) -> IoResult<()> {
+    let path = fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().into());
+    let path = path.as_path();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("not a file: {}", path.display()))?
+        .to_string_lossy();
+    let tmp_path =
+        path.with_file_name(format!(".{}.{}.manifmt", file_name, std::process::id()));
+
+    let written = (|| -> IoResult<()> {
+        let mut fd = OpenOptions::new().write(true).create_new(true).open(&tmp_path)?;
+        fd.write_all(toml.as_bytes())?;
+        fd.sync_all()?;
+
+        if let Ok(meta) = fs::metadata(path) {
+            fs::set_permissions(&tmp_path, meta.permissions())?;
+        }
+        if backup && path.exists() {
+            fs::copy(path, path.with_file_name(format!("{}.orig", file_name)))?;
+        }
+        fs::rename(&tmp_path, path).map_err(Into::into)
+    })();
+
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    written
 }
@@ -0,0 +1,173 @@
+//! Removing the tables that say nothing, see `clean_tables`.
+//!
+//! Only the empty tables `is_droppable` knows to mean nothing are dropped, any other
+//! empty table, like a `[dependencies.name]` or the `[workspace]` marking the root of a
+//! workspace, may mean something to cargo and stays. A header with nothing but other
+//! tables under it is left out. A `[dependencies.name]` table is written as
+//! `name = { .. }` in its dependency table when that table has keys of its own and the
+//! line fits in `max_width`. Tables with comments in or around them and the ones a
+//! directive covers are left as written.
+
+use std::collections::HashSet;
+
+use toml_edit::{Document, InlineTable, Item, Key, Table};
+
+use super::{
+    comments::{prefix_of, visit_headers, Prefix},
+    Directives,
+};
+use crate::{decor::has_comment, Config};
+
+/// Drops the empty tables and the redundant headers of `toml` and merges the split
+/// dependency tables into their parent.
+pub(super) fn clean_tables(
+    toml: &mut Document,
+    config: &Config,
+    directives: &Directives,
+) {
+    let footers = footers(toml);
+    let cleaner = Cleaner { config, directives, footers };
+    cleaner.table(&mut vec![], toml.as_table_mut());
+}
+
+/// Whether the table at the keys of `path` means nothing to cargo when it is empty,
+/// which is the case for `[features]`, `[badges]` and the dependency tables themselves.
+pub(crate) fn is_droppable(path: &[String]) -> bool {
+    let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+    matches!(
+        path.as_slice(),
+        ["features"
+            | "badges"
+            | "dependencies"
+            | "dev-dependencies"
+            | "build-dependencies"]
+            | ["target", _, "dependencies" | "dev-dependencies" | "build-dependencies"]
+    )
+}
+
+struct Cleaner<'a> {
+    config: &'a Config,
+    directives: &'a Directives,
+    /// The dotted paths of the tables followed by comment lines of their own.
+    footers: HashSet<String>,
+}
+
+impl Cleaner<'_> {
+    /// Cleans the tables under `table`, the one at the keys of `segments`, innermost
+    /// first so a table that only held dropped tables goes as well.
+    fn table(&self, segments: &mut Vec<String>, table: &mut Table) {
+        let path = segments.join(".");
+        let keys = table.iter().map(|(key, _)| key.to_owned()).collect::<Vec<_>>();
+        let merges = self.merges_into(&path, table);
+        for key in keys {
+            let Some(Item::Table(child)) = table.get_mut(&key) else {
+                continue;
+            };
+            if child.is_dotted() {
+                continue;
+            }
+            segments.push(key.clone());
+            let held_tables = !child.is_empty();
+            self.table(segments, child);
+            let droppable = is_droppable(segments);
+            let child_path = segments.join(".");
+            segments.pop();
+            if self.is_kept(&path, &child_path, child) {
+                continue;
+            }
+
+            if child.is_empty() {
+                // A table that only held dropped tables goes with them
+                if held_tables || droppable {
+                    table.remove(&key);
+                }
+            } else if !child.iter().any(|(_, item)| item.is_value()) {
+                if !child.is_implicit() {
+                    drop_header(child);
+                }
+            } else if let Some(inline) = self.inline(&key, child).filter(|_| merges) {
+                table[key.as_str()] = Item::Value(inline.into());
+                if let Some(decor) = table.key_decor_mut(&key) {
+                    decor.clear();
+                }
+            }
+        }
+    }
+
+    /// Whether the table at `path`, in the table at `parent`, has to stay as it is.
+    fn is_kept(&self, parent: &str, path: &str, table: &Table) -> bool {
+        self.directives.is_table_skipped(parent)
+            || self.directives.is_table_pinned(path)
+            || (!table.is_implicit() && has_comment(table.decor()))
+            || self.footers.contains(path)
+    }
+
+    /// Whether the tables under `table`, at the dotted `path`, may become inline tables
+    /// of it, which is when it is a dependency table with a header and keys of its own.
+    fn merges_into(&self, path: &str, table: &Table) -> bool {
+        path.ends_with("dependencies")
+            && !table.is_implicit()
+            && table.iter().any(|(_, item)| item.is_value())
+            && !self.directives.is_table_pinned(path)
+    }
+
+    /// The dependency `table` written as an inline table, when it fits on one line and
+    /// has no comments.
+    fn inline(&self, key: &str, table: &Table) -> Option<InlineTable> {
+        for (name, item) in table.iter() {
+            let value = item.as_value()?;
+            let key_decor = table.key_decor(name)?;
+            if has_comment(key_decor) || has_comment(value.decor()) {
+                return None;
+            }
+        }
+
+        let mut inline = table.clone().into_inline_table();
+        inline.fmt();
+        let line = format!("{} = {}", Key::new(key).display_repr(), inline);
+        let fits = !line.contains('\n') && line.chars().count() <= self.config.max_width;
+        fits.then_some(inline)
+    }
+}
+
+/// Leaves out the header of `table`, the blank lines above it go to the table written
+/// first under it.
+fn drop_header(table: &mut Table) {
+    let prefix = prefix_of(Some(table.decor())).to_owned();
+    table.set_implicit(true);
+    let first = table
+        .iter_mut()
+        .filter_map(|(_, item)| item.as_table_mut())
+        .filter(|t| !t.is_dotted() && !t.is_implicit())
+        .min_by_key(|t| t.position());
+    if let Some(first) = first {
+        if !has_comment(first.decor()) {
+            first.decor_mut().set_prefix(prefix);
+        }
+    }
+}
+
+/// The dotted paths of the tables with comment lines directly below their last line,
+/// `toml_edit` keeps those in the prefix of the next header.
+fn footers(toml: &mut Document) -> HashSet<String> {
+    let mut headers = vec![];
+    visit_headers(toml.as_table_mut(), &mut |path, table| {
+        let prefix = prefix_of(Some(table.decor())).to_owned();
+        headers.push((table.position(), path.to_owned(), prefix));
+    });
+    headers.sort_by_key(|(pos, ..)| *pos);
+
+    let mut footers = HashSet::new();
+    for pair in headers.windows(2) {
+        if !Prefix::parse(&pair[1].2).footer.is_empty() {
+            footers.insert(pair[0].1.clone());
+        }
+    }
+    let trailing = toml.trailing().as_str().unwrap_or_default();
+    if let Some((_, path, _)) = headers.last() {
+        if trailing.lines().next().is_some_and(|l| l.trim_start().starts_with('#')) {
+            footers.insert(path.clone());
+        }
+    }
+    footers
+}
@@ -0,0 +1,88 @@
+//! How keys and array elements are compared when sorting.
+
+use std::{borrow::Cow, cmp::Ordering, iter::Peekable, str::Chars};
+
+use crate::{config::Collation, Config};
+
+/// Compares strings following the `sort_collation` of a config.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Collator {
+    collation: Collation,
+    dash_underscore_equal: bool,
+}
+
+impl Collator {
+    pub(super) fn new(config: &Config) -> Self {
+        Self {
+            collation: config.sort_collation,
+            dash_underscore_equal: config.dash_underscore_equal,
+        }
+    }
+
+    /// Compares `a` and `b`, strings the collation considers equal are ordered by
+    /// their bytes so the order is always the same.
+    pub(super) fn cmp(&self, a: &str, b: &str) -> Ordering {
+        let (na, nb) = (self.normalize(a), self.normalize(b));
+        let ord = match self.collation {
+            Collation::Ascii => na.cmp(&nb),
+            Collation::CaseInsensitive => {
+                let na = na.chars().map(|c| c.to_ascii_lowercase());
+                na.cmp(nb.chars().map(|c| c.to_ascii_lowercase()))
+            }
+            Collation::Natural => natural(&na, &nb),
+        };
+        ord.then_with(|| a.cmp(b))
+    }
+
+    /// Compares two lists of strings one element at a time.
+    pub(super) fn cmp_all(&self, a: &[String], b: &[String]) -> Ordering {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| self.cmp(a, b))
+            .find(|ord| ord.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len()))
+    }
+
+    fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        if self.dash_underscore_equal && s.contains('_') {
+            Cow::Owned(s.replace('_', "-"))
+        } else {
+            Cow::Borrowed(s)
+        }
+    }
+}
+
+/// Compares runs of digits by their value and everything else ignoring case, so
+/// `crate-2` comes before `crate-10`.
+fn natural(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        let ord = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (digits(&mut a), digits(&mut b));
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (Some(x), Some(y)) => {
+                let ord = x.to_ascii_lowercase().cmp(&y.to_ascii_lowercase());
+                a.next();
+                b.next();
+                ord
+            }
+        };
+        if ord.is_ne() {
+            return ord;
+        }
+    }
+}
+
+fn digits(chars: &mut Peekable<Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
+}
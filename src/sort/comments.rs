@@ -0,0 +1,319 @@
+//! Which comments belong to which key or table, so sorting moves every comment along
+//! with its owner.
+//!
+//! `toml_edit` keeps the lines between two keys in the prefix of the second key, and
+//! the lines after the last key of a table in the prefix of the next header. Those
+//! lines are split by owner:
+//!
+//! ```toml
+//! [dependencies]
+//! # Header comments, above the first key and followed by a blank line, stay on top.
+//!
+//! # Leading comments, directly above a key, move with it.
+//! a = "1" # Trailing comments, on the same line, move with the value.
+//! # Footer comments, directly below a key and followed by a blank line, move with
+//! # the key above them. Below the last key they belong to the table.
+//!
+//! b = "1"
+//! ```
+
+use std::collections::{BTreeSet, HashMap};
+
+use toml_edit::{Decor, Document, Item, RawString, Table, Value};
+
+use super::directives::is_boundary;
+use crate::decor::{is_key_value, last_value_mut};
+
+/// The prefix of a key or header split by the owner of each part.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(super) struct Prefix {
+    /// Comment lines directly below the previous key and followed by a blank line.
+    pub(super) footer: String,
+    /// Blank lines and the comment blocks standing on their own between them.
+    pub(super) detached: String,
+    /// Comment lines directly above the key, ending with its indentation.
+    pub(super) leading: String,
+}
+
+impl Prefix {
+    pub(super) fn parse(raw: &str) -> Self {
+        let lines = raw.split_inclusive('\n').collect::<Vec<_>>();
+        let leading_start = lines.iter().rposition(|l| is_blank(l)).map_or(0, |i| i + 1);
+        let footer_end = match lines.iter().position(|l| !is_comment(l)) {
+            Some(idx) if is_blank(lines[idx]) => idx,
+            _ => 0,
+        };
+        Self {
+            footer: lines[..footer_end].concat(),
+            detached: lines[footer_end..leading_start].concat(),
+            leading: lines[leading_start..].concat(),
+        }
+    }
+}
+
+fn is_blank(line: &str) -> bool { line.ends_with('\n') && line.trim().is_empty() }
+
+fn is_comment(line: &str) -> bool { line.trim_start().starts_with('#') }
+
+fn has_comment(raw: &str) -> bool { raw.lines().any(is_comment) }
+
+pub(super) fn prefix_of(decor: Option<&Decor>) -> &str {
+    decor.and_then(|d| d.prefix()).and_then(RawString::as_str).unwrap_or_default()
+}
+
+/// Puts `footer` in front of `rest`, keeping the blank line that set it apart.
+fn join_footer(footer: &str, rest: &str) -> String {
+    if footer.is_empty() || rest.split_inclusive('\n').next().is_some_and(is_blank) {
+        return format!("{}{}", footer, rest);
+    }
+    let newline = if footer.ends_with("\r\n") { "\r\n" } else { "\n" };
+    format!("{}{}{}", footer, newline, rest)
+}
+
+/// Splits the `off` and `on` directives off the top of the `leading` comments, they
+/// stay at the start of the group instead of moving with the key.
+fn split_directives(leading: &str) -> (&str, &str) {
+    let lines = leading.split_inclusive('\n').collect::<Vec<_>>();
+    let end = lines.iter().rposition(|l| is_boundary(l)).map_or(0, |i| i + 1);
+    leading.split_at(lines[..end].iter().map(|l| l.len()).sum())
+}
+
+/// Appends `footer` after the value, on the lines below it.
+fn push_footer(val: &mut Value, footer: &str) {
+    if footer.is_empty() {
+        return;
+    }
+    // Only the innermost value of a dotted key is written out
+    if let Some(dotted) = val.as_inline_table_mut().filter(|t| t.is_dotted()) {
+        let last = dotted.iter_mut().map(|(_, v)| v).last();
+        if let Some(last) = last {
+            return push_footer(last, footer);
+        }
+    }
+    let suffix = val.decor().suffix().and_then(RawString::as_str).unwrap_or_default();
+    let newline = if footer.ends_with("\r\n") { "\r\n" } else { "\n" };
+    let footer = footer.strip_suffix(newline).unwrap_or(footer);
+    let suffix = format!("{}{}{}", suffix, newline, footer);
+    val.decor_mut().set_suffix(suffix);
+}
+
+/// Moves the keys of `table` into the order of `groups`.
+///
+/// Each group is a run of keys that was set apart from the one before it by a blank
+/// line or an `off` or `on` directive, the comments above the first key of a group
+/// and the directives stay above the group. Keys left out of `groups` go last. Any
+/// other blank line is kept only while the keys on both sides of it still follow
+/// each other.
+pub(super) fn reorder(table: &mut Table, groups: &[Vec<String>]) {
+    let orig = table
+        .iter()
+        .filter(|(_, item)| is_key_value(item))
+        .map(|(k, _)| k.to_owned())
+        .collect::<Vec<_>>();
+    let group_of = groups
+        .iter()
+        .enumerate()
+        .flat_map(|(g, keys)| keys.iter().map(move |k| (k.as_str(), g)))
+        .collect::<HashMap<_, _>>();
+
+    // The group headers are found at the key that used to come first in its group
+    let mut seen = BTreeSet::new();
+    let mut headers = HashMap::new();
+    let mut footers = HashMap::new();
+    let mut prefixes = HashMap::new();
+    let mut blanks = HashMap::new();
+    for (idx, key) in orig.iter().enumerate() {
+        let prefix = Prefix::parse(prefix_of(table.key_decor(key)));
+        let group = group_of.get(key.as_str()).copied().unwrap_or(groups.len());
+        let starts_group = seen.insert(group);
+        let own = if idx == 0 {
+            let (directives, leading) = split_directives(&prefix.leading);
+            headers.insert(group, prefix.footer + &prefix.detached + directives);
+            leading.to_owned()
+        } else {
+            footers.insert(orig[idx - 1].clone(), prefix.footer);
+            if starts_group {
+                let (directives, leading) = split_directives(&prefix.leading);
+                headers.insert(group, prefix.detached + directives);
+                leading.to_owned()
+            } else if has_comment(&prefix.detached) {
+                prefix.detached + &prefix.leading
+            } else {
+                blanks.insert(key.clone(), (&orig[idx - 1], prefix.detached));
+                prefix.leading
+            }
+        };
+        prefixes.insert(key.clone(), own);
+    }
+
+    let rank = groups.iter().flatten().enumerate().map(|(idx, k)| (k.clone(), idx));
+    let rank = rank.collect::<HashMap<_, _>>();
+    table.sort_values_by(|a, _, b, _| {
+        let rank = |k: &str| rank.get(k).copied().unwrap_or(usize::MAX);
+        rank(a.get()).cmp(&rank(b.get()))
+    });
+
+    let sorted = table
+        .iter()
+        .filter(|(_, item)| is_key_value(item))
+        .map(|(k, _)| k.to_owned())
+        .collect::<Vec<_>>();
+    let mut footer = String::new();
+    let mut last_group = None;
+    let mut prev = None;
+    for key in &sorted {
+        let group = group_of.get(key.as_str()).copied().unwrap_or(groups.len());
+        let mut prefix = String::new();
+        if last_group != Some(group) {
+            prefix.push_str(&headers.remove(&group).unwrap_or_default());
+            last_group = Some(group);
+        } else if let Some((_, blank)) = blanks.get(key).filter(|(p, _)| Some(*p) == prev)
+        {
+            prefix.push_str(blank);
+        }
+        prev = Some(key);
+        prefix.push_str(&prefixes.remove(key).unwrap_or_default());
+        let prefix = join_footer(&std::mem::take(&mut footer), &prefix);
+        if let Some(decor) = table.key_decor_mut(key) {
+            decor.set_prefix(prefix);
+        }
+        footer = footers.remove(key).unwrap_or_default();
+    }
+    match sorted.last().and_then(|k| table.get_mut(k)) {
+        Some(Item::Value(val)) => push_footer(val, &footer),
+        Some(Item::Table(tab)) => {
+            if let Some(val) = last_value_mut(tab) {
+                push_footer(val, &footer)
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Moves the footer comments of every table, found in the prefix of the header that
+/// follows it, to the end of the table so they stay with it when tables are moved.
+///
+/// Returns the comments at the top of the document, `restore_document_header` puts
+/// them back above whichever table comes first once the tables are in order.
+pub(super) fn attach_table_footers(toml: &mut Document) -> String {
+    let mut prefixes = vec![];
+    visit_headers(toml.as_table_mut(), &mut |_, table| {
+        prefixes.push((table.position(), prefix_of(Some(table.decor())).to_owned()));
+    });
+    let mut order = (0..prefixes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&idx| prefixes[idx].0);
+
+    // The footer of a table is in the prefix of the header that comes after it, the
+    // root table has no header so its footer is put after its last value right away
+    let root_has_values = toml.iter().any(|(_, item)| item.is_value());
+    let mut footers = vec![String::new(); prefixes.len()];
+    let mut rests = vec![None; prefixes.len()];
+    let mut doc_header = String::new();
+    for (pos, &idx) in order.iter().enumerate() {
+        let prefix = Prefix::parse(&prefixes[idx].1);
+        if pos == 0 && !root_has_values {
+            let newline = if prefix.detached.contains("\r\n") { "\r\n" } else { "\n" };
+            doc_header = prefix.footer + &prefix.detached;
+            rests[idx] = Some(format!("{}{}", newline, prefix.leading));
+        } else if !prefix.footer.is_empty() {
+            match pos.checked_sub(1) {
+                Some(prev) => footers[order[prev]] = prefix.footer,
+                None => match last_value_mut(toml.as_table_mut()) {
+                    Some(val) => push_footer(val, &prefix.footer),
+                    None => continue,
+                },
+            }
+            rests[idx] = Some(prefix.detached + &prefix.leading);
+        }
+    }
+    // The comments right after the last key of the document belong to the last table
+    if let Some(&last) = order.last() {
+        let trailing = toml.trailing().as_str().unwrap_or_default().to_owned();
+        let lines = trailing.split_inclusive('\n').collect::<Vec<_>>();
+        let end = lines.iter().position(|l| !is_comment(l)).unwrap_or(lines.len());
+        if end > 0 {
+            footers[last] = lines[..end].concat();
+            toml.set_trailing(lines[end..].concat());
+        }
+    }
+
+    let mut idx = 0;
+    visit_headers(toml.as_table_mut(), &mut |_, table| {
+        if let Some(rest) = rests[idx].take() {
+            table.decor_mut().set_prefix(rest);
+        }
+        let footer = std::mem::take(&mut footers[idx]);
+        match last_value_mut(table) {
+            Some(val) => push_footer(val, &footer),
+            None if !footer.is_empty() => {
+                let suffix = table.decor().suffix().and_then(RawString::as_str);
+                let newline = if footer.ends_with("\r\n") { "\r\n" } else { "\n" };
+                let footer = footer.strip_suffix(newline).unwrap_or(&footer);
+                let suffix =
+                    format!("{}{}{}", suffix.unwrap_or_default(), newline, footer);
+                table.decor_mut().set_suffix(suffix);
+            }
+            None => {}
+        }
+        idx += 1;
+    });
+    doc_header
+}
+
+/// Puts the comments taken by `attach_table_footers` back at the top of the
+/// document, above the table that comes first now.
+pub(super) fn restore_document_header(toml: &mut Document, doc_header: &str) {
+    if toml.iter().any(|(_, item)| item.is_value()) {
+        return;
+    }
+    let mut first = None;
+    visit_headers(toml.as_table_mut(), &mut |_, table| {
+        first = first.min(table.position()).or(table.position());
+    });
+    visit_headers(toml.as_table_mut(), &mut |_, table| {
+        if table.position() != first {
+            return;
+        }
+        let prefix = Prefix::parse(prefix_of(Some(table.decor())));
+        let detached = if has_comment(&prefix.detached) { &prefix.detached } else { "" };
+        table
+            .decor_mut()
+            .set_prefix(format!("{}{}{}", doc_header, detached, prefix.leading));
+    });
+}
+
+/// Calls `f` on every table that is written with a `[header]` along with its dotted
+/// path, in a fixed order. The entries of an array of tables share its path.
+pub(crate) fn visit_headers(table: &mut Table, f: &mut impl FnMut(&str, &mut Table)) {
+    visit_headers_at(table, &mut String::new(), f);
+}
+
+fn visit_headers_at(
+    table: &mut Table,
+    path: &mut String,
+    f: &mut impl FnMut(&str, &mut Table),
+) {
+    for (key, item) in table.iter_mut() {
+        let len = path.len();
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(key.get());
+        match item {
+            Item::Table(tab) if !tab.is_dotted() => {
+                if !tab.is_implicit() {
+                    f(path, tab);
+                }
+                visit_headers_at(tab, path, f);
+            }
+            Item::ArrayOfTables(arr) => {
+                for tab in arr.iter_mut() {
+                    f(path, tab);
+                    visit_headers_at(tab, path, f);
+                }
+            }
+            _ => {}
+        }
+        path.truncate(len);
+    }
+}
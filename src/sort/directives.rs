@@ -0,0 +1,256 @@
+//! The `# manifmt: skip` and `# manifmt: off` / `# manifmt: on` comment directives.
+//!
+//! `# manifmt: skip` directly above a key, or after its value on the same line, leaves
+//! that key where it is and its value as written. Above a table header it does the
+//! same for the whole table. Every key and table between `# manifmt: off` and
+//! `# manifmt: on` is left alone like that, a region that is never turned back on
+//! runs to the end of the document. The `off` and `on` lines themselves never move,
+//! the keys of a table are only sorted within the runs between them.
+
+use std::collections::{BTreeSet, HashSet};
+
+use toml_edit::{Array, Decor, Document, RawString, Table};
+
+use super::comments::{prefix_of, visit_headers, Prefix};
+
+/// A comment directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Directive {
+    Skip,
+    Off,
+    On,
+}
+
+impl Directive {
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let comment = line.trim().strip_prefix('#')?;
+        let directive = comment.trim().strip_prefix("manifmt:")?;
+        match directive.trim() {
+            "skip" => Some(Self::Skip),
+            "off" => Some(Self::Off),
+            "on" => Some(Self::On),
+            _ => None,
+        }
+    }
+}
+
+/// The tables and keys of a document that must be left as they are.
+#[derive(Debug, Default)]
+pub(crate) struct Directives {
+    /// The dotted paths of the tables that keep their place.
+    tables: HashSet<String>,
+    /// The dotted paths of the tables that also keep all of their keys as written.
+    skipped: HashSet<String>,
+    /// The pinned keys, by the dotted path of their table and their name.
+    keys: HashSet<(String, String)>,
+    /// The keys with an `off` or `on` directive above them, by the dotted path of
+    /// their table and their name.
+    boundaries: HashSet<(String, String)>,
+}
+
+/// The keys of one table that sorting has to work around.
+#[derive(Debug, Default)]
+pub(super) struct Pinned {
+    /// The keys that keep their place and value.
+    keys: BTreeSet<String>,
+    /// The keys that start a run after an `off` or `on` directive.
+    boundaries: BTreeSet<String>,
+}
+
+impl Pinned {
+    /// Whether `key` keeps its place and value.
+    pub(super) fn contains(&self, key: &str) -> bool { self.keys.contains(key) }
+
+    /// Splits `keys`, in the order they are written, into the runs between the `off`
+    /// and `on` directives, each run is sorted on its own.
+    pub(super) fn runs(&self, keys: &[String]) -> Vec<Vec<String>> {
+        let mut runs: Vec<Vec<String>> = vec![];
+        for key in keys {
+            match runs.last_mut() {
+                Some(run) if !self.boundaries.contains(key) => run.push(key.clone()),
+                _ => runs.push(vec![key.clone()]),
+            }
+        }
+        runs
+    }
+}
+
+impl Directives {
+    /// Finds the directives in `toml`, walking it in the order it is written.
+    pub(crate) fn find(toml: &mut Document) -> Self {
+        let mut tables =
+            vec![(None, String::new(), String::new(), keys_of(toml.as_table()))];
+        visit_headers(toml.as_table_mut(), &mut |path, table| {
+            let prefix = prefix_of(Some(table.decor())).to_owned();
+            tables.push((table.position(), path.to_owned(), prefix, keys_of(table)));
+        });
+        tables.sort_by_key(|(pos, ..)| *pos);
+
+        let mut found = Self::default();
+        let mut off = false;
+        for (_, path, prefix, keys) in tables {
+            let (in_off, skip) = scan(prefix_lines(&prefix), &mut off);
+            if in_off || skip {
+                found.tables.insert(path.clone());
+            }
+            if skip {
+                found.skipped.insert(path.clone());
+            }
+            for (key, prefix, suffix) in keys {
+                let (in_off, skip) = scan(prefix_lines(&prefix), &mut off);
+                let skip_after =
+                    suffix.lines().any(|l| Directive::parse(l) == Some(Directive::Skip));
+                if prefix.lines().any(is_boundary) {
+                    found.boundaries.insert((path.clone(), key.clone()));
+                }
+                if in_off || skip || skip_after {
+                    found.keys.insert((path.clone(), key));
+                }
+            }
+        }
+        found
+    }
+
+    /// Whether the table at the dotted `path` keeps its place.
+    pub(crate) fn is_table_pinned(&self, path: &str) -> bool {
+        self.tables.contains(path)
+    }
+
+    /// Whether the table at the dotted `path` keeps its place and all of its keys.
+    pub(crate) fn is_table_skipped(&self, path: &str) -> bool {
+        self.skipped.contains(path)
+    }
+
+    /// The keys of the table at the dotted `path` that keep their place and value, and
+    /// the ones the `off` and `on` directives start a run at.
+    pub(super) fn pinned_keys(&self, path: &str) -> Pinned {
+        let of_table = |keys: &HashSet<(String, String)>| {
+            keys.iter()
+                .filter(|(table, _)| table == path)
+                .map(|(_, k)| k.clone())
+                .collect()
+        };
+        Pinned { keys: of_table(&self.keys), boundaries: of_table(&self.boundaries) }
+    }
+
+    /// Whether the key of the table at the dotted `path` keeps its place and value.
+    pub(crate) fn is_key_pinned(&self, path: &str, key: &str) -> bool {
+        self.is_table_skipped(path)
+            || self.keys.contains(&(path.to_owned(), key.to_owned()))
+    }
+
+    /// The positions of the tables, in the order `visit_headers` finds them.
+    pub(super) fn positions(toml: &mut Document) -> Vec<Option<usize>> {
+        let mut positions = vec![];
+        visit_headers(toml.as_table_mut(), &mut |_, table| {
+            positions.push(table.position())
+        });
+        positions
+    }
+
+    /// Moves the pinned tables back to where they were before the tables were
+    /// reordered, the other tables fill the places in between in their new order.
+    pub(super) fn restore_table_positions(
+        &self,
+        toml: &mut Document,
+        before: &[Option<usize>],
+    ) {
+        let mut pinned = vec![];
+        visit_headers(toml.as_table_mut(), &mut |path, _| {
+            pinned.push(self.is_table_pinned(path))
+        });
+        if !pinned.contains(&true) {
+            return;
+        }
+        let after = Self::positions(toml);
+
+        let mut old_order = (0..before.len()).collect::<Vec<_>>();
+        old_order.sort_by_key(|&idx| before[idx]);
+        let mut new_order =
+            (0..after.len()).filter(|&idx| !pinned[idx]).collect::<Vec<_>>();
+        new_order.sort_by_key(|&idx| after[idx]);
+        let mut new_order = new_order.into_iter();
+
+        let mut slots = before.to_vec();
+        slots.sort();
+        let mut positions = vec![None; before.len()];
+        for (slot, &old) in slots.into_iter().zip(&old_order) {
+            let idx = if pinned[old] { Some(old) } else { new_order.next() };
+            if let Some(idx) = idx {
+                positions[idx] = slot;
+            }
+        }
+        let mut idx = 0;
+        visit_headers(toml.as_table_mut(), &mut |_, table| {
+            if let Some(pos) = positions[idx] {
+                table.set_position(pos);
+            }
+            idx += 1;
+        });
+    }
+}
+
+/// Whether any element of `arr` carries a directive, such arrays are not sorted.
+pub(super) fn has_directive(arr: &Array) -> bool {
+    let decor_lines = |d: &Decor| {
+        [d.prefix(), d.suffix()]
+            .iter()
+            .flatten()
+            .flat_map(|raw| raw.as_str())
+            .flat_map(str::lines)
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>()
+    };
+    let trailing = arr.trailing().as_str().unwrap_or_default();
+    arr.iter()
+        .flat_map(|v| decor_lines(v.decor()))
+        .chain(trailing.lines().map(ToOwned::to_owned))
+        .any(|l| Directive::parse(&l).is_some())
+}
+
+/// Whether `line` is an `off` or `on` directive, those lines stay where they are.
+pub(super) fn is_boundary(line: &str) -> bool {
+    matches!(Directive::parse(line), Some(Directive::Off | Directive::On))
+}
+
+/// Follows the `off` and `on` directives of `lines` and returns whether the item
+/// after them is in a region that is off and whether a `skip` is right above it.
+fn scan<'a>(lines: (Vec<&'a str>, Vec<&'a str>), off: &mut bool) -> (bool, bool) {
+    let (before, leading) = lines;
+    for line in before.iter().chain(&leading) {
+        match Directive::parse(line) {
+            Some(Directive::Off) => *off = true,
+            Some(Directive::On) => *off = false,
+            _ => {}
+        }
+    }
+    (*off, leading.iter().any(|l| Directive::parse(l) == Some(Directive::Skip)))
+}
+
+/// The lines of a prefix, split into the ones set apart from the item by a blank line
+/// and the ones directly above it.
+fn prefix_lines(prefix: &str) -> (Vec<&str>, Vec<&str>) {
+    let split = Prefix::parse(prefix);
+    let before_len = split.footer.len() + split.detached.len();
+    (prefix[..before_len].lines().collect(), prefix[before_len..].lines().collect())
+}
+
+/// The name, prefix and value suffix of every key written in `table`.
+fn keys_of(table: &Table) -> Vec<(String, String, String)> {
+    table
+        .iter()
+        .filter(|(_, item)| {
+            item.is_value() || item.as_table().is_some_and(Table::is_dotted)
+        })
+        .map(|(key, item)| {
+            let prefix = prefix_of(table.key_decor(key)).to_owned();
+            let suffix = item
+                .as_value()
+                .and_then(|v| v.decor().suffix())
+                .and_then(RawString::as_str)
+                .unwrap_or_default()
+                .to_owned();
+            (key.to_owned(), prefix, suffix)
+        })
+        .collect()
+}
@@ -0,0 +1,151 @@
+//! The `dependency_groups` that dependencies are ordered by before their name.
+
+use regex::Regex;
+use toml_edit::{Item, Table};
+
+use super::{is_git, is_path, is_ws_dep};
+use crate::Config;
+
+/// One pattern of a dependency group.
+#[derive(Debug, Clone)]
+pub(crate) enum DependencyRule {
+    /// A glob matching the name of the dependency, `acme-*`.
+    Glob(glob::Pattern),
+    /// A regular expression between slashes matching the name, `/^tokio(-.*)?$/`.
+    Regex(Regex),
+    /// `@path`, dependencies with a `path` key.
+    Path,
+    /// `@git`, dependencies with a `git` key.
+    Git,
+    /// `@workspace`, dependencies inherited from the workspace.
+    Workspace,
+}
+
+impl DependencyRule {
+    pub(crate) fn parse(pat: &str) -> Result<Self, String> {
+        if let Some(attr) = pat.strip_prefix('@') {
+            return match attr {
+                "path" => Ok(Self::Path),
+                "git" => Ok(Self::Git),
+                "workspace" => Ok(Self::Workspace),
+                _ => Err(format!(
+                    "unknown attribute `@{}`, expected `@path`, `@git` or `@workspace`",
+                    attr
+                )),
+            };
+        }
+        match pat.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            Some(re) => Regex::new(re).map(Self::Regex).map_err(|e| e.to_string()),
+            None => glob::Pattern::new(pat).map(Self::Glob).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn matches(&self, key: &str, item: &Item) -> bool {
+        let table = item.as_table_like();
+        match self {
+            Self::Glob(pat) => pat.matches(key),
+            Self::Regex(re) => re.is_match(key),
+            Self::Path => table.is_some_and(is_path),
+            Self::Git => table.is_some_and(is_git),
+            Self::Workspace => table.is_some_and(is_ws_dep),
+        }
+    }
+}
+
+/// The compiled `dependency_groups` of a config.
+#[derive(Debug, Default)]
+pub(super) struct DepGroups {
+    groups: Vec<(String, Vec<DependencyRule>)>,
+    headers: bool,
+}
+
+impl DepGroups {
+    /// Compiles the groups of `config`, patterns that do not parse are ignored since
+    /// loading the config already reported them.
+    pub(super) fn new(config: &Config) -> Self {
+        let groups = config
+            .dependency_groups
+            .0
+            .iter()
+            .map(|(name, pats)| {
+                let rules = pats.iter().flat_map(|p| DependencyRule::parse(p)).collect();
+                (name.clone(), rules)
+            })
+            .collect();
+        Self { groups, headers: config.dependency_group_headers }
+    }
+
+    /// The index of the first group matching the dependency, the dependencies no
+    /// group matches come after all of them.
+    pub(super) fn group_of(&self, key: &str, item: &Item) -> usize {
+        self.groups
+            .iter()
+            .position(|(_, rules)| rules.iter().any(|r| r.matches(key, item)))
+            .unwrap_or(self.groups.len())
+    }
+
+    /// Removes the `# <group>` headers written by `add_headers`, together with the
+    /// blank lines above them.
+    pub(super) fn strip_headers(&self, table: &mut Table) {
+        if !self.headers || self.groups.is_empty() {
+            return;
+        }
+        let keys = table.iter().map(|(k, _)| k.to_owned()).collect::<Vec<_>>();
+        for key in keys {
+            let Some(decor) = table.key_decor_mut(&key) else { continue };
+            let Some(prefix) = decor.prefix().and_then(|p| p.as_str()) else {
+                continue;
+            };
+            let mut lines = vec![];
+            for line in prefix.split_inclusive('\n') {
+                if self.is_header(line) {
+                    while lines.last().is_some_and(|l: &&str| l.trim().is_empty()) {
+                        lines.pop();
+                    }
+                } else {
+                    lines.push(line);
+                }
+            }
+            let stripped = lines.concat();
+            if stripped != prefix {
+                decor.set_prefix(stripped);
+            }
+        }
+    }
+
+    /// Writes a blank line and a `# <group>` comment above the first dependency of
+    /// every group, and a blank line above the ungrouped ones.
+    pub(super) fn add_headers(&self, table: &mut Table, skip: impl Fn(&str) -> bool) {
+        if !self.headers || self.groups.is_empty() {
+            return;
+        }
+        let keys = table
+            .iter()
+            .map(|(k, item)| (k.to_owned(), self.group_of(k, item)))
+            .collect::<Vec<_>>();
+        let mut last = None;
+        for (idx, (key, group)) in keys.iter().enumerate() {
+            if last == Some(*group) {
+                continue;
+            }
+            last = Some(*group);
+            if skip(key) {
+                continue;
+            }
+            let Some(decor) = table.key_decor_mut(key) else { continue };
+            let prefix = decor.prefix().and_then(|p| p.as_str()).unwrap_or_default();
+            let comments = prefix.trim_start_matches(|c: char| c.is_whitespace());
+            let blank = if idx == 0 { "" } else { "\n" };
+            let header = match self.groups.get(*group) {
+                Some((name, _)) => format!("{}# {}\n{}", blank, name, comments),
+                None => format!("{}{}", blank, comments),
+            };
+            decor.set_prefix(header);
+        }
+    }
+
+    fn is_header(&self, line: &str) -> bool {
+        let comment = line.trim().strip_prefix('#').map(str::trim);
+        comment.is_some_and(|c| self.groups.iter().any(|(name, _)| name == c))
+    }
+}
@@ -1,7 +1,34 @@
-use std::{cmp::Ordering, collections::BTreeMap, iter::FromIterator};
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+use toml_edit::{
+    Array,
+    ArrayOfTables,
+    Document,
+    Item,
+    Table,
+    TableLike,
+    TomlError,
+    Value,
+};
 
-use toml_edit::{Array, Document, Item, Table, TableLike, Value};
+pub(crate) use self::{
+    cleanup::is_droppable,
+    comments::visit_headers,
+    directives::{Directive, Directives},
+    groups::DependencyRule,
+};
+use self::{collation::Collator, directives::Pinned, groups::DepGroups};
+use crate::{
+    decor::{has_comment, has_comment_raw},
+    Config,
+};
 
+mod cleanup;
+mod collation;
+mod comments;
+mod directives;
+mod groups;
 mod test;
 
 /// Each `Matcher` field when matched to a heading or key token
@@ -12,6 +39,10 @@ pub struct Matcher<'a> {
     /// Toml heading with braces `[heading]` and the key
     /// of the array to sort.
     pub heading_key: &'a [(&'a str, &'a str)],
+    /// Whether the matched tables whose name ends in `dependencies` hold cargo
+    /// dependencies, those are sorted by `dependency_groups` and kind of dependency
+    /// instead of only by name.
+    pub cargo_dependencies: bool,
 }
 
 pub const MATCHER: Matcher<'_> = Matcher {
@@ -21,8 +52,13 @@ pub const MATCHER: Matcher<'_> = Matcher {
         ("workspace", "exclude"),
         ("workspace", "dependencies"),
     ],
+    cargo_dependencies: true,
 };
 
+/// The dotted paths of the arrays `sort_rustflags` sorts.
+pub const RUSTFLAGS: &[&str] =
+    &["build.rustflags", "host.rustflags", "target.*.rustflags"];
+
 /// A state machine to track collection of headings.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 enum Heading {
@@ -34,6 +70,18 @@ enum Heading {
     Complete(Vec<String>),
 }
 
+impl Heading {
+    /// Orders headings like the derived `Ord`, comparing the segments with `collator`.
+    fn cmp_with(&self, other: &Self, collator: Collator) -> std::cmp::Ordering {
+        match (self, other) {
+            (Self::Next(a), Self::Next(b)) | (Self::Complete(a), Self::Complete(b)) => {
+                collator.cmp_all(a, b)
+            }
+            _ => self.cmp(other),
+        }
+    }
+}
+
 fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
     if table.is_empty() && !table.is_implicit() {
         let next = match keys.pop().unwrap() {
@@ -45,7 +93,7 @@ fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
     for (head, item) in table.iter() {
         match item {
             Item::Value(_) => {
-                if keys.last().map_or(false, |h| matches!(h, Heading::Complete(_))) {
+                if keys.last().is_some_and(|h| matches!(h, Heading::Complete(_))) {
                     continue;
                 }
                 let next = match keys.pop().unwrap() {
@@ -76,82 +124,197 @@ fn gather_headings(table: &Table, keys: &mut Vec<Heading>, depth: usize) {
                 keys.push(next);
                 gather_headings(table, keys, depth + 1);
             }
-            Item::ArrayOfTables(_arr) => unreachable!("no [[heading]] are sorted"),
+            // The entries of `[[heading]]` keep their place among the tables
+            Item::ArrayOfTables(_) => continue,
             Item::None => unreachable!("an empty table will not be sorted"),
         }
     }
 }
 
-fn sort_by_group(table: &mut Table) {
-    let table_clone = table.clone();
-    table.clear();
-    let mut groups = BTreeMap::new();
-    let mut curr = 0;
-    for (idx, (k, v)) in table_clone.iter().enumerate() {
-        let decor = table.key_decor(k);
-
-        if decor.map_or(0, count_blank_lines) > 0 {
-            groups.entry(idx).or_insert_with(|| vec![(k, v)]);
-            curr = idx;
-        } else {
-            groups.entry(curr).or_default().push((k, v));
+fn sort_by_group(table: &mut Table, pinned: &Pinned, collator: Collator) {
+    let mut groups: Vec<Vec<String>> = vec![];
+    for (k, _) in table.iter() {
+        match groups.last_mut() {
+            Some(group) if table.key_decor(k).map_or(0, count_blank_lines) == 0 => {
+                group.push(k.to_owned())
+            }
+            _ => groups.push(vec![k.to_owned()]),
         }
     }
+    let groups = groups
+        .iter()
+        .flat_map(|group| pinned.runs(group))
+        .map(|run| {
+            let sorted = run.iter().cloned().sorted_by(|a, b| collator.cmp(a, b));
+            pin(&run, sorted.collect(), pinned)
+        })
+        .collect::<Vec<_>>();
+    comments::reorder(table, &groups);
+}
 
-    for (_, mut group) in groups {
-        group.sort_by_key(|x| x.0);
+/// Sorts the keys of `table`, moving their comments along with them.
+fn sort_keys(table: &mut Table, pinned: &Pinned, collator: Collator) {
+    let keys = table.iter().map(|(k, _)| k.to_owned()).collect::<Vec<_>>();
+    let runs = pinned
+        .runs(&keys)
+        .into_iter()
+        .map(|run| {
+            let sorted = run.iter().cloned().sorted_by(|a, b| collator.cmp(a, b));
+            pin(&run, sorted.collect(), pinned)
+        })
+        .collect::<Vec<_>>();
+    comments::reorder(table, &runs);
+}
 
-        for (k, v) in group {
-            table.insert(k, v.clone());
+/// Keeps the `pinned` keys at their index in `orig`, the other keys fill the places
+/// left in the order of `sorted`.
+fn pin(orig: &[String], sorted: Vec<String>, pinned: &Pinned) -> Vec<String> {
+    let mut rest = sorted.into_iter().filter(|k| !pinned.contains(k));
+    orig.iter()
+        .flat_map(|k| if pinned.contains(k) { Some(k.clone()) } else { rest.next() })
+        .collect()
+}
 
-            // Transfer key decor from cloned table to modified table. Apparently
-            // inserting v.clone() does not work like that.
-            if let (Some(decor_mut), Some(decor)) =
-                (table.key_decor_mut(k), table_clone.key_decor(k))
-            {
-                if let Some(prefix) = decor.prefix() {
-                    decor_mut.set_prefix(prefix.clone());
-                }
+fn sort_array(arr: &mut Array, collator: Collator) {
+    if !arr.iter().all(Value::is_str) || directives::has_directive(arr) {
+        return;
+    }
+    // The whitespace around elements stays in place unless there are comments, those
+    // have to move along with the element they belong to
+    let keep_decor = !arr.iter().any(|v| has_comment(v.decor()));
+    let decors = arr.iter().map(|v| v.decor().clone()).collect::<Vec<_>>();
 
-                if let Some(suffix) = decor.suffix() {
-                    decor_mut.set_suffix(suffix.clone());
+    arr.sort_by(|a, b| {
+        collator.cmp(a.as_str().unwrap_or_default(), b.as_str().unwrap_or_default())
+    });
+    if keep_decor {
+        for (val, decor) in arr.iter_mut().zip(decors) {
+            *val.decor_mut() = decor;
+        }
+    }
+}
+
+/// Sorts the entries of an array of tables by the string at `key`, the entries take
+/// over the positions of the ones they replace so they stay where they were in the
+/// document.
+///
+/// The comments above an entry's header belong to it and move along with it. When
+/// an entry is missing the key the array is left as is.
+fn sort_array_of_tables(arr: &mut ArrayOfTables, key: &str, collator: Collator) {
+    if !arr.iter().all(|t| t.get(key).and_then(Item::as_str).is_some()) {
+        return;
+    }
+    let positions = arr.iter().map(Table::position).collect::<Vec<_>>();
+    let mut tables = arr.iter().cloned().collect::<Vec<_>>();
+    tables.sort_by(|a, b| {
+        collator
+            .cmp(a[key].as_str().unwrap_or_default(), b[key].as_str().unwrap_or_default())
+    });
+
+    arr.clear();
+    for (mut table, pos) in tables.into_iter().zip(positions) {
+        if let Some(pos) = pos {
+            table.set_position(pos);
+        }
+        arr.push(table);
+    }
+}
+
+/// Sorts the `rustflags` arrays of a `.cargo/config.toml`, found under `[build]`,
+/// `[host]` and every `[target.<triple>]` table.
+///
+/// A flag and the values following it, like `"-C", "target-cpu=native"`, are kept
+/// together and sorted as one.
+pub fn sort_rustflags(toml: &mut Document, config: &Config) {
+    let directives = Directives::find(toml);
+    let collator = Collator::new(config);
+    for heading in ["build", "host"] {
+        if let Some(arr) = toml
+            .get_mut(heading)
+            .and_then(|t| t.get_mut("rustflags"))
+            .and_then(Item::as_array_mut)
+        {
+            let path = format!("{}.rustflags", heading);
+            if config.order_sensitive(&path).is_none()
+                && !directives.is_key_pinned(heading, "rustflags")
+            {
+                sort_flags(arr, collator);
+            }
+        }
+    }
+    if let Some(targets) = toml.get_mut("target").and_then(Item::as_table_like_mut) {
+        for (triple, target) in targets.iter_mut() {
+            let table = format!("target.{}", triple.get());
+            let path = format!("{}.rustflags", table);
+            if let Some(arr) = target.get_mut("rustflags").and_then(Item::as_array_mut) {
+                if config.order_sensitive(&path).is_none()
+                    && !directives.is_key_pinned(&table, "rustflags")
+                {
+                    sort_flags(arr, collator);
                 }
             }
         }
     }
 }
 
-fn sort_array(arr: &mut Array) {
-    let mut all_strings = true;
-    let mut arr_copy = arr.iter().cloned().collect::<Vec<_>>();
-    arr_copy.sort_by(|a, b| match (a, b) {
-        (Value::String(a), Value::String(b)) => a.value().cmp(b.value()),
-        _ => {
-            all_strings = false;
-            Ordering::Equal
+fn sort_flags(arr: &mut Array, collator: Collator) {
+    if !arr.iter().all(Value::is_str) || directives::has_directive(arr) {
+        return;
+    }
+
+    let mut flags: Vec<Vec<Value>> = vec![];
+    for val in arr.iter() {
+        let is_flag = val.as_str().is_some_and(|s| s.starts_with('-'));
+        match flags.last_mut() {
+            Some(flag) if !is_flag => flag.push(val.clone()),
+            _ => flags.push(vec![val.clone()]),
         }
-    });
-    if all_strings {
-        *arr = Array::from_iter(arr_copy);
+    }
+    let key = |flag: &Vec<Value>| flag.iter().flat_map(Value::as_str).join(" ");
+    flags.sort_by(|a, b| collator.cmp(&key(a), &key(b)));
+
+    // Like `sort_array` the whitespace between elements stays where it was
+    let decors = arr.iter().map(|v| v.decor().clone()).collect::<Vec<_>>();
+    let keep_decor = !decors.iter().any(has_comment);
+    arr.clear();
+    for (idx, mut val) in flags.into_iter().flatten().enumerate() {
+        if keep_decor {
+            *val.decor_mut() = decors[idx].clone();
+        }
+        arr.push_formatted(val);
     }
 }
+
 /// check if the dependency value indicates that it is a workspace dep
-fn is_ws_dep(t: &dyn TableLike) -> bool {
+pub(super) fn is_ws_dep(t: &dyn TableLike) -> bool {
     t.get("workspace").and_then(|ws| ws.as_bool()).is_some()
 }
 fn is_onekey(t: &dyn TableLike) -> bool { t.len() == 1 }
-fn is_git(t: &dyn TableLike) -> bool { t.contains_key("git") }
-fn is_path(t: &dyn TableLike) -> bool { t.contains_key("path") }
+pub(super) fn is_git(t: &dyn TableLike) -> bool { t.contains_key("git") }
+pub(super) fn is_path(t: &dyn TableLike) -> bool { t.contains_key("path") }
 
-/// Returns a sorted toml `Document`.
+/// Returns a sorted toml `Document`, or the parse error if `input` is not valid toml.
+///
+/// Arrays matching the config's `order_sensitive_arrays` are never reordered, nor is
+/// anything pinned by a `# manifmt: skip` or `# manifmt: off` comment.
 pub fn sort_toml(
     input: &str,
     matcher: Matcher<'_>,
     group: bool,
     ordering: &[String],
-) -> Document {
+    config: &Config,
+) -> Result<Document, TomlError> {
     let mut ordering = ordering.to_owned();
-    let mut toml = input.parse::<Document>().unwrap();
+    let mut toml = input.parse::<Document>()?;
+    let mut directives = Directives::find(&mut toml);
+    if config.clean_tables {
+        // Parsed again so the tables left are numbered as if they were written that way
+        cleanup::clean_tables(&mut toml, config, &directives);
+        toml = toml.to_string().parse::<Document>()?;
+        directives = Directives::find(&mut toml);
+    }
+    let collator = Collator::new(config);
+    let dep_groups = DepGroups::new(config);
 
     // This takes care of `[workspace] members = [...]`
     // and the [workspace.dependencies] table
@@ -162,15 +325,20 @@ pub fn sort_toml(
         if let Some((_k, Item::Table(table))) =
             toml.as_table_mut().get_key_value_mut(heading)
         {
+            let path = format!("{}.{}", heading, key);
             match table.get_key_value_mut(key) {
-                Some((_, Item::Value(Value::Array(arr)))) => {
-                    sort_array(arr);
+                _ if directives.is_key_pinned(heading, key) => {}
+                Some((_, Item::Value(Value::Array(arr))))
+                    if config.order_sensitive(&path).is_none() =>
+                {
+                    sort_array(arr, collator);
                 }
-                Some((_, Item::Table(tab))) => {
-                    if key.ends_with("dependencies") {
-                        sort_deps(tab);
+                Some((_, Item::Table(tab))) if !directives.is_table_skipped(&path) => {
+                    let pinned = directives.pinned_keys(&path);
+                    if matcher.cargo_dependencies && key.ends_with("dependencies") {
+                        sort_deps(tab, &pinned, collator, &dep_groups);
                     } else {
-                        tab.sort_values();
+                        sort_keys(tab, &pinned, collator);
                     }
                 }
                 _ => {}
@@ -178,7 +346,18 @@ pub fn sort_toml(
         }
     }
 
-    let mut first_table = None;
+    if config.sort_array_of_tables {
+        for (head, item) in toml.as_table_mut().iter_mut() {
+            if let Item::ArrayOfTables(arr) = item {
+                if config.order_sensitive(head.get()).is_none()
+                    && !directives.is_table_pinned(head.get())
+                {
+                    sort_array_of_tables(arr, &config.array_of_tables_key, collator);
+                }
+            }
+        }
+    }
+
     let mut heading_order: BTreeMap<_, Vec<Heading>> = BTreeMap::new();
     for (idx, (head, item)) in toml.as_table_mut().iter_mut().enumerate() {
         if !matcher.heading.contains(&head.display_repr().as_ref()) {
@@ -191,10 +370,6 @@ pub fn sort_toml(
         }
         match item {
             Item::Table(table) => {
-                if first_table.is_none() {
-                    // The root table is always index 0 which we ignore so add 1
-                    first_table = Some(idx + 1);
-                }
                 let headings = heading_order.entry((idx, head.to_string())).or_default();
                 // Push a `Heading::Complete` here incase the tables are ordered
                 // [heading.segs]
@@ -203,13 +378,18 @@ pub fn sort_toml(
                 headings.push(Heading::Complete(vec![head.to_string()]));
 
                 gather_headings(table, headings, 1);
-                headings.sort();
-                if head.to_string().ends_with("dependencies") {
-                    sort_deps(table);
+                headings.sort_by(|a, b| a.cmp_with(b, collator));
+                let pinned = directives.pinned_keys(head.get());
+                if directives.is_table_skipped(head.get()) {
+                    // Left as written
+                } else if matcher.cargo_dependencies
+                    && head.to_string().ends_with("dependencies")
+                {
+                    sort_deps(table, &pinned, collator, &dep_groups);
                 } else if group {
-                    sort_by_group(table);
+                    sort_by_group(table, &pinned, collator);
                 } else {
-                    table.sort_values()
+                    sort_keys(table, &pinned, collator);
                 }
             }
             Item::None => continue,
@@ -217,40 +397,53 @@ pub fn sort_toml(
         }
     }
 
+    let doc_header = comments::attach_table_footers(&mut toml);
+    let positions = Directives::positions(&mut toml);
     if ordering.is_empty() {
-        sort_lexicographical(first_table, &heading_order, &mut toml);
+        sort_lexicographical(&heading_order, &mut toml);
     } else {
         sort_by_ordering(&ordering, &heading_order, &mut toml);
     }
+    directives.restore_table_positions(&mut toml, &positions);
+    comments::restore_document_header(&mut toml, &doc_header);
 
-    toml
+    Ok(toml)
 }
 
+/// Sorts the nested tables of every heading among the places they already take, the
+/// top level tables and everything between them keep their place.
 fn sort_lexicographical(
-    first_table: Option<usize>,
     heading_order: &BTreeMap<(usize, String), Vec<Heading>>,
     toml: &mut Document,
 ) {
-    // Since the root table is always index 0 we add one
-    let first_table_idx = first_table.unwrap_or_default() + 1;
-    for (idx, heading) in heading_order.iter().flat_map(|(_, segs)| segs).enumerate() {
-        if let Heading::Complete(segs) = heading {
-            let mut nested = 0;
-            let mut table = Some(toml.as_table_mut());
-            for seg in segs {
-                nested += 1;
-                table = table.and_then(|t| t[seg].as_table_mut());
-            }
-            // Do not reorder the unsegmented tables
-            if nested > 1 {
-                if let Some(table) = table {
-                    table.set_position(first_table_idx + idx);
-                }
+    for headings in heading_order.values() {
+        let nested = headings
+            .iter()
+            .filter_map(|heading| match heading {
+                Heading::Complete(segs) if segs.len() > 1 => Some(segs),
+                _ => None,
+            })
+            .filter(|segs| table_at(toml, segs).and_then(|t| t.position()).is_some())
+            .collect_vec();
+        let mut slots = nested
+            .iter()
+            .filter_map(|segs| table_at(toml, segs).and_then(|t| t.position()))
+            .collect_vec();
+        slots.sort_unstable();
+        for (segs, pos) in nested.into_iter().zip(slots) {
+            if let Some(table) = table_at(toml, segs) {
+                table.set_position(pos);
             }
         }
     }
 }
 
+/// The table at the keys `segs`, when there is one.
+fn table_at<'a>(toml: &'a mut Document, segs: &[String]) -> Option<&'a mut Table> {
+    segs.iter()
+        .try_fold(toml.as_table_mut(), |table, seg| table.get_mut(seg)?.as_table_mut())
+}
+
 fn sort_by_ordering(
     ordering: &[String],
     heading_order: &BTreeMap<(usize, String), Vec<Heading>>,
@@ -319,66 +512,70 @@ pub(crate) fn count_blank_lines(decor: &toml_edit::Decor) -> usize {
         .count()
 }
 
-fn sort_deps(table: &mut Table) {
-    use itertools::Itertools;
-
-    let groups: Vec<Vec<String>> = {
-        // iterator of meta & key
-        let key_meta = table.iter().map(|e| (DepMeta::from_entry(&e), e.0));
-
-        // sorted iter of meta & key (group_by only works when pre-sorted)
-        let sorted_by_meta = key_meta.sorted_by_key(|(m, _s)| *m);
-
-        // grouped by meta
-        let grouped_by_meta = sorted_by_meta.group_by(|(m, _k)| *m);
-
-        // sort the items in each group, lexically
-        let grouped_and_sorted_items = grouped_by_meta.into_iter().map(|(_, group)| {
-            let iter = group.map(|(_m, k)| k.to_string()).sorted();
-            iter.collect_vec()
-        });
-
-        grouped_and_sorted_items.collect()
-    };
-
-    for group in groups {
-        for k in group {
-            let Some(orig_decor) = table.key_decor(&k).map(ToOwned::to_owned) else {
-                tracing::warn!("Unable to find key decor for {k} in table");
-                continue;
-            };
-            let Some((k, mut v)) = table.remove_entry(&k) else {
-                tracing::warn!("Unable to find entry for {k} in table");
-                continue;
-            };
-            // todo: factor this somewhere else
-            // transform single key tables to inline tables
-            let mut dotted = false;
-            if let Some(t) = v.as_inline_table_mut() {
-                dotted = true;
-                t.decor_mut().clear();
-                // avoid any extra spaces from when it was a normal table
-                for (mut k, _) in t.iter_mut() {
-                    k.decor_mut().clear();
-                }
-                if t.len() == 1 {
-                    t.set_dotted(true);
-                }
+fn sort_deps(
+    table: &mut Table,
+    pinned: &Pinned,
+    collator: Collator,
+    dep_groups: &DepGroups,
+) {
+    // ordered by group, then by kind of dependency, then by name
+    let rank = table
+        .iter()
+        .map(|e| {
+            (e.0.to_owned(), (dep_groups.group_of(e.0, e.1), DepMeta::from_entry(&e)))
+        })
+        .collect::<HashMap<_, _>>();
+
+    dep_groups.strip_headers(table);
+    for (mut k, v) in table.iter_mut() {
+        if pinned.contains(k.get()) {
+            continue;
+        }
+        // todo: factor this somewhere else
+        // transform single key tables to inline tables
+        if let Some(t) = v.as_inline_table_mut() {
+            // The comment after the value stays, the whitespace is reset
+            let trailing = t.decor().suffix().filter(|s| has_comment_raw(s)).cloned();
+            t.decor_mut().clear();
+            if let Some(trailing) = trailing {
+                t.decor_mut().set_suffix(trailing);
             }
-            table.insert(&k, v);
-            let d = table.key_decor_mut(&k).unwrap();
-
-            if let Some(pfx) = orig_decor.prefix() {
-                d.set_prefix(pfx.to_owned())
+            // avoid any extra spaces from when it was a normal table
+            for (mut k, _) in t.iter_mut() {
+                k.decor_mut().clear();
             }
-
-            if let Some(od) = orig_decor.suffix() {
-                if !dotted {
-                    d.set_suffix(od.to_owned())
+            if t.len() == 1 {
+                t.set_dotted(true);
+                // A dotted key is written as its only value, which takes the comment
+                let trailing = t.decor().suffix().cloned();
+                for (_, v) in t.iter_mut() {
+                    v.decor_mut().clear();
+                    if let Some(trailing) = &trailing {
+                        v.decor_mut().set_suffix(trailing.clone());
+                    }
                 }
             }
+            let prefix = k.decor().prefix().cloned();
+            k.decor_mut().clear();
+            if let Some(prefix) = prefix {
+                k.decor_mut().set_prefix(prefix);
+            }
         }
     }
+    let keys = table.iter().map(|(k, _)| k.to_owned()).collect::<Vec<_>>();
+    let runs = pinned
+        .runs(&keys)
+        .into_iter()
+        .map(|run| {
+            let sorted = run
+                .iter()
+                .cloned()
+                .sorted_by(|a, b| rank[a].cmp(&rank[b]).then_with(|| collator.cmp(a, b)));
+            pin(&run, sorted.collect(), pinned)
+        })
+        .collect_vec();
+    comments::reorder(table, &runs);
+    dep_groups.add_headers(table, |key| pinned.contains(key));
 }
 
 #[derive(PartialEq, Eq, Debug, PartialOrd, Ord, Hash, Copy, Clone)]
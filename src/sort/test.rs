@@ -3,7 +3,11 @@ use std::fs;
 
 use pretty_assertions::{assert_eq, assert_ne};
 
-use super::Matcher;
+use super::{collation::Collator, comments::Prefix, Matcher};
+use crate::{
+    config::{Collation, DependencyGroups},
+    Config,
+};
 
 const MATCHER: Matcher<'_> = Matcher {
     heading: &["dependencies", "dev-dependencies", "build-dependencies"],
@@ -12,33 +16,34 @@ const MATCHER: Matcher<'_> = Matcher {
         ("workspace", "exclude"),
         ("workspace", "dependencies"),
     ],
+    cargo_dependencies: true,
 };
 
 #[test]
 fn toml_edit_check() {
     let input = fs::read_to_string("fixtures/workspace.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, false, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, false, &[], &Config::new()).unwrap();
     assert_ne!(input, sorted.to_string());
 }
 
 #[test]
 fn grouped_check() {
     let input = fs::read_to_string("fixtures/ruma.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, true, &[], &Config::new()).unwrap();
     assert_ne!(input, sorted.to_string());
 }
 
 #[test]
 fn sort_correct() {
     let input = fs::read_to_string("fixtures/right.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, true, &[], &Config::new()).unwrap();
     assert_eq!(input.replace("\r\n", "\n"), sorted.to_string());
 }
 
 #[test]
 fn sort_tables() {
     let input = fs::read_to_string("fixtures/fend.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, true, &[], &Config::new()).unwrap();
     assert_ne!(input, sorted.to_string());
 }
 
@@ -46,12 +51,12 @@ fn sort_tables() {
 fn sort_devfirst() {
     let input = fs::read_to_string("fixtures/reorder.toml").unwrap();
 
-    let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, true, &[], &Config::new()).unwrap();
     let sorted = sorted.to_string();
     assert_eq!(input.replace("\r\n", "\n"), sorted.to_string());
 
     let input = fs::read_to_string("fixtures/noreorder.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, true, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, true, &[], &Config::new()).unwrap();
     assert_eq!(input.replace("\r\n", "\n"), sorted.to_string());
 }
 
@@ -69,13 +74,544 @@ fn reorder() {
             "build-dependencies".to_owned(),
             "dev-dependencies".to_owned(),
         ],
-    );
+        &Config::new(),
+    )
+    .unwrap();
     assert_ne!(input, sorted.to_string());
 }
 
 #[test]
 fn workspace_dependencies_check() {
     let input = fs::read_to_string("fixtures/workspace_dep.toml").unwrap();
-    let sorted = super::sort_toml(&input, MATCHER, false, &[]);
+    let sorted = super::sort_toml(&input, MATCHER, false, &[], &Config::new()).unwrap();
     assert_ne!(input, sorted.to_string());
 }
+
+#[test]
+fn sort_rustflags_keeps_flag_values() {
+    let input = r#"[build]
+rustflags = ["-C", "target-cpu=native", "--cfg", "tokio_unstable", "-Dwarnings"]
+
+[target.x86_64-unknown-linux-gnu]
+rustflags = [
+    "-C", "link-arg=-fuse-ld=lld",
+    "-C", "force-frame-pointers=yes",
+]
+"#;
+    let mut config = Config::new();
+    config.order_sensitive_arrays.clear();
+    let mut toml = input.parse().unwrap();
+    super::sort_rustflags(&mut toml, &config);
+    assert_eq!(
+        toml.to_string(),
+        r#"[build]
+rustflags = ["--cfg", "tokio_unstable", "-C", "target-cpu=native", "-Dwarnings"]
+
+[target.x86_64-unknown-linux-gnu]
+rustflags = [
+    "-C", "force-frame-pointers=yes",
+    "-C", "link-arg=-fuse-ld=lld",
+]
+"#
+    );
+}
+
+#[test]
+fn order_sensitive_arrays_keep_their_order() {
+    let input = r#"[build]
+rustflags = ["-Dwarnings", "-C", "target-cpu=native"]
+
+[workspace]
+members = ["b", "a"]
+exclude = ["d", "c"]
+"#;
+    let mut config = Config::new();
+    let mut toml = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    super::sort_rustflags(&mut toml, &config);
+    assert_eq!(
+        toml.to_string(),
+        r#"[build]
+rustflags = ["-Dwarnings", "-C", "target-cpu=native"]
+
+[workspace]
+members = ["a", "b"]
+exclude = ["c", "d"]
+"#
+    );
+
+    config.order_sensitive_arrays = vec!["workspace.mem*".to_owned()];
+    let mut toml = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    super::sort_rustflags(&mut toml, &config);
+    assert_eq!(
+        toml.to_string(),
+        r#"[build]
+rustflags = ["-C", "target-cpu=native", "-Dwarnings"]
+
+[workspace]
+members = ["b", "a"]
+exclude = ["c", "d"]
+"#
+    );
+}
+
+#[test]
+fn sort_array_of_tables_by_name() {
+    let input = r#"[package]
+name = "foo"
+
+[[bin]]
+name = "zeta"
+path = "src/zeta.rs"
+
+# The main binary
+[[bin]]
+name = "alpha"
+
+[[example]]
+path = "examples/no_name.rs"
+
+[[example]]
+name = "basic"
+
+[dependencies]
+serde = "1"
+"#;
+    let mut config = Config::new();
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(sorted.to_string(), input);
+
+    config.sort_array_of_tables = true;
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[package]
+name = "foo"
+
+# The main binary
+[[bin]]
+name = "alpha"
+
+[[bin]]
+name = "zeta"
+path = "src/zeta.rs"
+
+[[example]]
+path = "examples/no_name.rs"
+
+[[example]]
+name = "basic"
+
+[dependencies]
+serde = "1"
+"#
+    );
+}
+
+#[test]
+fn split_prefix_by_owner() {
+    let prefix = Prefix::parse("# footer\n\n# detached\n\n# leading\n    ");
+    assert_eq!(
+        prefix,
+        Prefix {
+            footer: "# footer\n".to_owned(),
+            detached: "\n# detached\n\n".to_owned(),
+            leading: "# leading\n    ".to_owned(),
+        }
+    );
+    // Without a blank line after them the comments lead the key
+    let prefix = Prefix::parse("# leading\n");
+    assert_eq!(prefix.footer, "");
+    assert_eq!(prefix.leading, "# leading\n");
+}
+
+#[test]
+fn comments_move_with_their_owner() {
+    let input = fs::read_to_string("fixtures/comments.toml").unwrap();
+    let expected = fs::read_to_string("fixtures/comments.sorted.toml").unwrap();
+    let config = Config::new();
+    let sorted =
+        super::sort_toml(&input, MATCHER, false, &config.table_order, &config).unwrap();
+    assert_eq!(sorted.to_string(), expected);
+
+    let again = super::sort_toml(&expected, MATCHER, false, &config.table_order, &config)
+        .unwrap();
+    assert_eq!(again.to_string(), expected);
+}
+
+#[test]
+fn directives_pin_items() {
+    let input = r#"[package]
+name = "foo"
+
+[dev-dependencies]
+b = "1"
+a = "1"
+
+# manifmt: skip
+[dependencies]
+z = "1"
+y = "1"
+
+[build-dependencies]
+d = "1"
+# manifmt: skip
+c = { version = "1" }
+b = "1"
+a = "1"
+
+[workspace]
+members = [
+    # manifmt: skip
+    "b",
+    "a",
+]
+# manifmt: skip
+exclude = ["d", "c"]
+"#;
+    let config = Config::new();
+    let sorted =
+        super::sort_toml(input, MATCHER, false, &config.table_order, &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[package]
+name = "foo"
+
+[build-dependencies]
+a = "1"
+# manifmt: skip
+c = { version = "1" }
+b = "1"
+d = "1"
+
+# manifmt: skip
+[dependencies]
+z = "1"
+y = "1"
+
+[dev-dependencies]
+a = "1"
+b = "1"
+
+[workspace]
+members = [
+    # manifmt: skip
+    "b",
+    "a",
+]
+# manifmt: skip
+exclude = ["d", "c"]
+"#
+    );
+}
+
+#[test]
+fn directives_turn_sorting_off_and_on() {
+    let input = r#"[dependencies]
+b = "1"
+a = "1"
+# manifmt: off
+z = "1"
+y = "1"
+
+[dev-dependencies]
+d = "1"
+# manifmt: on
+c = "1"
+f = "1"
+e = "1"
+"#;
+    let config = Config::new();
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[dependencies]
+a = "1"
+b = "1"
+# manifmt: off
+z = "1"
+y = "1"
+
+[dev-dependencies]
+d = "1"
+# manifmt: on
+c = "1"
+e = "1"
+f = "1"
+"#
+    );
+}
+
+#[test]
+fn directives_split_a_table_into_runs() {
+    let input = r#"[dependencies]
+zz = "1"
+# manifmt: off
+yy = "1"
+# manifmt: on
+bb = "1"
+aa = "1"
+"#;
+    let config = Config::new();
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[dependencies]
+zz = "1"
+# manifmt: off
+yy = "1"
+# manifmt: on
+aa = "1"
+bb = "1"
+"#
+    );
+}
+
+#[test]
+fn collations() {
+    let mut config = Config::new();
+    fn sorted<'a>(config: &Config, keys: &[&'a str]) -> Vec<&'a str> {
+        let collator = Collator::new(config);
+        let mut keys = keys.to_vec();
+        keys.sort_by(|a, b| collator.cmp(a, b));
+        keys
+    }
+    let keys = ["crate-10", "Serde", "crate-2", "anyhow", "crate_3"];
+    assert_eq!(
+        sorted(&config, &keys),
+        ["Serde", "anyhow", "crate-10", "crate-2", "crate_3"]
+    );
+
+    config.sort_collation = Collation::CaseInsensitive;
+    assert_eq!(
+        sorted(&config, &keys),
+        ["anyhow", "crate-10", "crate-2", "crate_3", "Serde"]
+    );
+
+    config.sort_collation = Collation::Natural;
+    assert_eq!(
+        sorted(&config, &keys),
+        ["anyhow", "crate-2", "crate-10", "crate_3", "Serde"]
+    );
+
+    config.dash_underscore_equal = true;
+    assert_eq!(
+        sorted(&config, &keys),
+        ["anyhow", "crate-2", "crate_3", "crate-10", "Serde"]
+    );
+}
+
+#[test]
+fn collation_applies_to_keys_and_arrays() {
+    let input = r#"[dependencies]
+tokio-10 = "1"
+Serde = "1"
+tokio-2 = "1"
+
+[workspace]
+members = ["crate-10", "crate-2"]
+"#;
+    let mut config = Config::new();
+    config.sort_collation = Collation::Natural;
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[dependencies]
+Serde = "1"
+tokio-2 = "1"
+tokio-10 = "1"
+
+[workspace]
+members = ["crate-2", "crate-10"]
+"#
+    );
+}
+
+#[test]
+fn dependency_groups_come_first() {
+    let input = r#"[dependencies]
+anyhow = "1"
+tokio = "1"
+acme-db = { path = "../db", version = "1" }
+futures-util = "0.3"
+acme-core = "1"
+local = { path = "../local", version = "1" }
+tokio-util = "0.7"
+"#;
+    let mut config = Config::new();
+    config.dependency_groups = DependencyGroups(vec![
+        ("internal".to_owned(), vec!["acme-*".to_owned(), "@path".to_owned()]),
+        ("async".to_owned(), vec!["/^(tokio|futures)(-.*)?$/".to_owned()]),
+    ]);
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[dependencies]
+acme-db = { path = "../db", version = "1" }
+local = { path = "../local", version = "1" }
+acme-core = "1"
+futures-util = "0.3"
+tokio = "1"
+tokio-util = "0.7"
+anyhow = "1"
+"#
+    );
+
+    config.dependency_group_headers = true;
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    let expected = r#"[dependencies]
+# internal
+acme-db = { path = "../db", version = "1" }
+local = { path = "../local", version = "1" }
+acme-core = "1"
+
+# async
+futures-util = "0.3"
+tokio = "1"
+tokio-util = "0.7"
+
+anyhow = "1"
+"#;
+    assert_eq!(sorted.to_string(), expected);
+    let again = super::sort_toml(expected, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(again.to_string(), expected);
+
+    config.dependency_groups.0.swap(0, 1);
+    let regrouped = super::sort_toml(expected, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        regrouped.to_string(),
+        r#"[dependencies]
+# async
+futures-util = "0.3"
+tokio = "1"
+tokio-util = "0.7"
+
+# internal
+acme-db = { path = "../db", version = "1" }
+local = { path = "../local", version = "1" }
+acme-core = "1"
+
+anyhow = "1"
+"#
+    );
+}
+
+#[test]
+fn clean_tables_drops_empty_tables() {
+    let input = r#"[package]
+name = "foo"
+
+[features]
+
+[workspace]
+
+# Kept for later
+[badges]
+
+[lib]
+[lib.empty]
+
+[dependencies]
+anyhow = "1"
+
+[dependencies.serde]
+version = "1"
+features = ["derive"]
+
+[dependencies.local]
+path = "../local" # not published
+
+[dev-dependencies]
+
+[dev-dependencies.tokio]
+version = "1"
+
+[dev-dependencies.empty]
+
+[build-dependencies.cc]
+version = "1"
+# manifmt: skip
+[build-dependencies.empty]
+
+[target.'cfg(unix)'.dev-dependencies]
+"#;
+    let mut config = Config::new();
+    config.clean_tables = true;
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(
+        sorted.to_string(),
+        r#"[package]
+name = "foo"
+
+[workspace]
+
+# Kept for later
+[badges]
+
+[lib.empty]
+
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+anyhow = "1"
+
+[dependencies.local]
+path = "../local" # not published
+
+[dev-dependencies.empty]
+
+[dev-dependencies.tokio]
+version = "1"
+
+[build-dependencies.cc]
+version = "1"
+# manifmt: skip
+[build-dependencies.empty]
+"#
+    );
+
+    let input = "[profile]\n\n[profile.release]\nlto = true\n";
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(sorted.to_string(), "[profile.release]\nlto = true\n");
+
+    config.clean_tables = false;
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(sorted.to_string(), input);
+}
+
+#[test]
+fn empty_table_order_sorts_nested_tables_in_place() {
+    let input = r#"[dependencies]
+log = "0.4"
+
+[dependencies.serde]
+version = "1"
+
+[[bin]]
+name = "zeta"
+
+[[bin]]
+name = "alpha"
+
+[dependencies.clap]
+version = "4"
+"#;
+    let config = Config::new();
+    let sorted = super::sort_toml(input, MATCHER, false, &[], &config).unwrap();
+    let expected = r#"[dependencies]
+log = "0.4"
+
+[dependencies.clap]
+version = "4"
+
+[[bin]]
+name = "zeta"
+
+[[bin]]
+name = "alpha"
+
+[dependencies.serde]
+version = "1"
+"#;
+    assert_eq!(sorted.to_string(), expected);
+    let again = super::sort_toml(expected, MATCHER, false, &[], &config).unwrap();
+    assert_eq!(again.to_string(), expected);
+}
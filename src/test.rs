@@ -0,0 +1,125 @@
+#![cfg(test)]
+use std::{
+    env,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use pretty_assertions::assert_eq;
+
+use super::{par_map, sort_stdin, write_file, Args};
+
+/// A new empty directory under the system's temp directory for the test `name`.
+pub(crate) fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("manifmt-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn file_names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn write_file_replaces_the_manifest() {
+    let dir = temp_dir("write");
+    let path = dir.join("Cargo.toml");
+    fs::write(&path, "[package]\n").unwrap();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    }
+
+    write_file(&path, "[package]\nname = \"foo\"\n", false).unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[package]\nname = \"foo\"\n");
+    // The temporary file is renamed away and no backup is made
+    assert_eq!(file_names(&dir), ["Cargo.toml"]);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn write_file_keeps_a_backup() {
+    let dir = temp_dir("backup");
+    let path = dir.join("Cargo.toml");
+    fs::write(&path, "[package]\n").unwrap();
+
+    write_file(&path, "[package]\nname = \"foo\"\n", true).unwrap();
+    assert_eq!(file_names(&dir), ["Cargo.toml", "Cargo.toml.orig"]);
+    assert_eq!(fs::read_to_string(dir.join("Cargo.toml.orig")).unwrap(), "[package]\n");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[package]\nname = \"foo\"\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn write_file_follows_symlinks() {
+    let dir = temp_dir("symlink");
+    let target = dir.join("shared.toml");
+    let link = dir.join("Cargo.toml");
+    fs::write(&target, "[package]\n").unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+
+    write_file(&link, "[package]\nname = \"foo\"\n", false).unwrap();
+    assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_to_string(&target).unwrap(), "[package]\nname = \"foo\"\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn stdin_uses_the_config_of_its_filepath() {
+    let dir = temp_dir("stdin");
+    fs::create_dir(dir.join("member")).unwrap();
+    fs::write(
+        dir.join("member/tomlfmt.toml"),
+        "table_order = [\"dependencies\", \"package\"]\n",
+    )
+    .unwrap();
+    let manifest = dir.join("member/Cargo.toml");
+    let args = Args::parse_from([
+        "cargo-manifmt".as_ref(),
+        "--stdin".as_ref(),
+        "--stdin-filepath".as_ref(),
+        manifest.as_os_str(),
+    ]);
+
+    let input = "[package]\nname = \"foo\"\n\n[dependencies]\nb = \"1\"\na = \"1\"\n";
+    let mut output = vec![];
+    sort_stdin(&args, &dir, input.as_bytes(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "[dependencies]\na = \"1\"\nb = \"1\"\n\n[package]\nname = \"foo\"\n"
+    );
+    // The manifest is only named, nothing is written next to it
+    assert_eq!(file_names(&dir.join("member")), ["tomlfmt.toml"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn par_map_keeps_the_order_of_its_items() {
+    let items = (0..32u64).collect::<Vec<_>>();
+    // The first items take the longest so the threads finish them last
+    let work = |n: &u64| {
+        thread::sleep(Duration::from_millis(32 - n));
+        n * 2
+    };
+    let serial = par_map(&items, 1, work);
+    assert_eq!(serial, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    assert_eq!(par_map(&items, 8, work), serial);
+    assert_eq!(par_map(&items, 64, work), serial);
+}
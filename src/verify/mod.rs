@@ -0,0 +1,203 @@
+use std::{collections::HashSet, fmt::Write};
+
+use toml::Value;
+
+use crate::{kind::Kind, Config};
+
+mod test;
+
+/// Parses both documents with `toml` and returns the sorted paths at which their
+/// data differs.
+///
+/// Key order is never significant. Only the arrays sorting a `kind` file with
+/// `config` reorders on purpose are compared regardless of element order, every
+/// other array has to match element for element. When `clean_tables` applies the
+/// empty tables it drops are left out of both.
+pub(crate) fn semantic_diff(
+    original: &str,
+    rewritten: &str,
+    kind: Kind,
+    config: &Config,
+) -> Result<Vec<String>, toml::de::Error> {
+    let mut original: Value = toml::from_str(original)?;
+    let mut rewritten: Value = toml::from_str(rewritten)?;
+    if kind.clean_tables(config) {
+        for value in [&mut original, &mut rewritten] {
+            if let Value::Table(table) = value {
+                drop_empty_tables(table, &mut vec![]);
+            }
+        }
+    }
+
+    let sorted = sorted_arrays(&original, kind, config);
+    let mut diffs = vec![];
+    diff_values(&mut String::new(), &original, &rewritten, &sorted, &mut diffs);
+    diffs.sort();
+    Ok(diffs)
+}
+
+/// Returns an error naming every differing path when `rewritten` is not
+/// semantically equal to `original`.
+pub(crate) fn ensure_equivalent(
+    original: &str,
+    rewritten: &str,
+    kind: Kind,
+    config: &Config,
+) -> crate::IoResult<()> {
+    let diffs = semantic_diff(original, rewritten, kind, config)
+        .map_err(|e| format!("sorted output is not valid toml: {}", e))?;
+    if diffs.is_empty() {
+        return Ok(());
+    }
+
+    let mut msg = String::from("sorting changed the meaning of the document at:");
+    for path in diffs {
+        write!(msg, "\n    {}", path)?;
+    }
+    Err(msg.into())
+}
+
+/// The paths of the arrays of `doc` that sorting a `kind` file with `config` may
+/// reorder, written like the paths `diff_values` reports.
+fn sorted_arrays(doc: &Value, kind: Kind, config: &Config) -> HashSet<String> {
+    let mut paths = HashSet::new();
+    let mut add = |keys: &[&str]| {
+        if config.order_sensitive(&keys.join(".")).is_none() {
+            let mut path = String::new();
+            for key in keys {
+                push_key(&mut path, key);
+            }
+            paths.insert(path);
+        }
+    };
+
+    for (heading, key) in kind.matcher().heading_key {
+        add(&[heading, key]);
+    }
+    if kind == Kind::CargoConfig && config.sort_rustflags {
+        add(&["build", "rustflags"]);
+        add(&["host", "rustflags"]);
+        if let Some(targets) = doc.get("target").and_then(Value::as_table) {
+            for triple in targets.keys() {
+                add(&["target", triple, "rustflags"]);
+            }
+        }
+    }
+    if config.sort_array_of_tables {
+        if let Some(root) = doc.as_table() {
+            for (key, value) in root {
+                if value.as_array().is_some_and(|arr| all_tables(arr)) {
+                    add(&[key]);
+                }
+            }
+        }
+    }
+    paths
+}
+
+fn diff_values(
+    path: &mut String,
+    a: &Value,
+    b: &Value,
+    sorted: &HashSet<String>,
+    diffs: &mut Vec<String>,
+) {
+    match (a, b) {
+        (Value::Table(a), Value::Table(b)) => {
+            for (key, a_val) in a {
+                let len = path.len();
+                push_key(path, key);
+                match b.get(key) {
+                    Some(b_val) => diff_values(path, a_val, b_val, sorted, diffs),
+                    None => diffs.push(format!("{} (removed)", path)),
+                }
+                path.truncate(len);
+            }
+            for key in b.keys().filter(|k| !a.contains_key(*k)) {
+                let len = path.len();
+                push_key(path, key);
+                diffs.push(format!("{} (added)", path));
+                path.truncate(len);
+            }
+        }
+        (Value::Array(a), Value::Array(b))
+            if sorted.contains(path.as_str()) && all_strings(a) && all_strings(b) =>
+        {
+            let mut a = a.iter().flat_map(Value::as_str).collect::<Vec<_>>();
+            let mut b = b.iter().flat_map(Value::as_str).collect::<Vec<_>>();
+            a.sort_unstable();
+            b.sort_unstable();
+            if a != b {
+                diffs.push(path.clone());
+            }
+        }
+        (Value::Array(a), Value::Array(b))
+            if sorted.contains(path.as_str())
+                && all_tables(a)
+                && all_tables(b)
+                && is_permutation(a, b) => {}
+        (Value::Array(a), Value::Array(b)) => {
+            if a.len() != b.len() {
+                diffs.push(format!("{} (length {} != {})", path, a.len(), b.len()));
+                return;
+            }
+            for (idx, (a, b)) in a.iter().zip(b).enumerate() {
+                let len = path.len();
+                write!(path, "[{}]", idx).unwrap();
+                diff_values(path, a, b, sorted, diffs);
+                path.truncate(len);
+            }
+        }
+        (Value::Float(a), Value::Float(b)) if a.is_nan() && b.is_nan() => {}
+        (a, b) => {
+            if a != b {
+                diffs.push(path.clone());
+            }
+        }
+    }
+}
+
+/// Removes the empty tables `clean_tables` may drop, and the tables that held
+/// nothing but those, from the `table` at the keys of `path`.
+fn drop_empty_tables(table: &mut toml::Table, path: &mut Vec<String>) {
+    let mut dropped = vec![];
+    for (key, value) in table.iter_mut() {
+        let Value::Table(child) = value else {
+            continue;
+        };
+        path.push(key.clone());
+        let held_tables = !child.is_empty();
+        drop_empty_tables(child, path);
+        if child.is_empty() && (held_tables || crate::sort::is_droppable(path)) {
+            dropped.push(key.clone());
+        }
+        path.pop();
+    }
+    for key in dropped {
+        table.remove(&key);
+    }
+}
+
+fn all_strings(arr: &[Value]) -> bool { arr.iter().all(Value::is_str) }
+
+fn all_tables(arr: &[Value]) -> bool { arr.iter().all(Value::is_table) }
+
+/// Whether `b` holds the same elements as `a` in any order.
+fn is_permutation(a: &[Value], b: &[Value]) -> bool {
+    let mut unmatched = b.iter().collect::<Vec<_>>();
+    a.len() == b.len()
+        && a.iter().all(|a| match unmatched.iter().position(|b| a == *b) {
+            Some(idx) => {
+                unmatched.swap_remove(idx);
+                true
+            }
+            None => false,
+        })
+}
+
+fn push_key(path: &mut String, key: &str) {
+    if !path.is_empty() {
+        path.push('.');
+    }
+    path.push_str(&toml_edit::Key::new(key).display_repr());
+}
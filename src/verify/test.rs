@@ -0,0 +1,137 @@
+#![cfg(test)]
+use std::fs;
+
+use pretty_assertions::assert_eq;
+
+use super::semantic_diff;
+use crate::{kind::Kind, sort, Config};
+
+fn diff(input: &str, rewritten: &str, config: &Config) -> Vec<String> {
+    semantic_diff(input, rewritten, Kind::Cargo, config).unwrap()
+}
+
+#[test]
+fn sorted_fixtures_are_equivalent() {
+    for entry in fs::read_dir("fixtures").unwrap() {
+        let path = entry.unwrap().path();
+        if !path.is_file() {
+            continue;
+        }
+        let input = fs::read_to_string(&path).unwrap();
+        for clean_tables in [false, true] {
+            let config = Config { clean_tables, ..Config::new() };
+            let sorted =
+                sort::sort_toml(&input, sort::MATCHER, false, &[], &config).unwrap();
+            let diffs = diff(&input, &sorted.to_string(), &config);
+            assert!(diffs.is_empty(), "{}: {:?}", path.display(), diffs);
+        }
+    }
+}
+
+#[test]
+fn reports_changed_paths() {
+    let input = r#"
+[package]
+name = "foo"
+keywords = [1, "a"]
+
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+"#;
+    let rewritten = r#"
+[package]
+name = "foo"
+keywords = ["a", 1]
+
+[dependencies]
+serde = { version = "1" }
+toml = "0.8"
+"#;
+    assert_eq!(
+        diff(input, rewritten, &Config::new()),
+        vec![
+            "dependencies.serde.features (removed)",
+            "dependencies.toml (added)",
+            "package.keywords[0]",
+            "package.keywords[1]",
+        ]
+    );
+}
+
+#[test]
+fn sorted_string_arrays_ignore_order() {
+    let input = "[workspace]\nmembers = [\"b\", \"a\"]\n";
+    let rewritten = "[workspace]\nmembers = [\"a\", \"b\"]\n";
+    assert!(diff(input, rewritten, &Config::new()).is_empty());
+
+    let dropped = "[workspace]\nmembers = [\"a\"]\n";
+    assert_eq!(diff(input, dropped, &Config::new()), vec!["workspace.members"]);
+}
+
+#[test]
+fn other_arrays_keep_their_order() {
+    let input = r#"
+[[bin]]
+name = "a"
+required-features = ["b", "a"]
+
+[build]
+rustflags = ["-C", "opt-level=3"]
+"#;
+    let rewritten = r#"
+[[bin]]
+name = "a"
+required-features = ["a", "b"]
+
+[build]
+rustflags = ["opt-level=3", "-C"]
+"#;
+    assert_eq!(
+        diff(input, rewritten, &Config::new()),
+        vec![
+            "bin[0].required-features[0]",
+            "bin[0].required-features[1]",
+            "build.rustflags[0]",
+            "build.rustflags[1]",
+        ]
+    );
+
+    let config =
+        Config { sort_rustflags: true, order_sensitive_arrays: vec![], ..Config::new() };
+    let diffs = semantic_diff(input, rewritten, Kind::CargoConfig, &config).unwrap();
+    assert_eq!(diffs, vec!["bin[0].required-features[0]", "bin[0].required-features[1]"]);
+}
+
+#[test]
+fn sorted_table_arrays_ignore_order() {
+    let input = "[[bin]]\nname = \"b\"\n\n[[bin]]\nname = \"a\"\n";
+    let rewritten = "[[bin]]\nname = \"a\"\n\n[[bin]]\nname = \"b\"\n";
+    assert_eq!(
+        diff(input, rewritten, &Config::new()),
+        vec!["bin[0].name", "bin[1].name"]
+    );
+
+    let config = Config { sort_array_of_tables: true, ..Config::new() };
+    assert!(diff(input, rewritten, &config).is_empty());
+    let renamed = "[[bin]]\nname = \"a\"\n\n[[bin]]\nname = \"c\"\n";
+    assert_eq!(diff(input, renamed, &config), vec!["bin[0].name", "bin[1].name"]);
+}
+
+#[test]
+fn clean_tables_may_drop_empty_tables() {
+    let clean = Config { clean_tables: true, ..Config::new() };
+    let input = "[workspace]\n\n[features]\n\n[target.'cfg(unix)'.dev-dependencies]\n";
+    let rewritten = "[workspace]\n";
+    assert!(diff(input, rewritten, &clean).is_empty());
+    assert_eq!(
+        diff(input, rewritten, &Config::new()),
+        vec!["features (removed)", "target (removed)"]
+    );
+    assert_eq!(diff(input, "", &clean), vec!["workspace (removed)"]);
+
+    let input = "[dependencies.foo]\n\n[lib.empty]\n\n[tool.x]\n";
+    assert_eq!(
+        diff(input, "", &clean),
+        vec!["dependencies (removed)", "lib (removed)", "tool (removed)"]
+    );
+}